@@ -5,7 +5,7 @@ echo '_TaaS_autocomplete() {
 	then
 		echo;	
 	else
-		autoc="$autoc del"
+		autoc="$autoc del resume reap"
 		autoc=$(echo $autoc | sed "s/common_data //")
 	fi
 	cur="${COMP_WORDS[COMP_CWORD]}"
@@ -90,16 +90,18 @@ fn generate_whole_vm_script(vm: &super::system::Vm, ws: &super::system::WholeSys
                 //nic_params.push((true, "subnet", &Some(nic.subnet)));
                 let private_ip = &nic.private_ip_address.to_string();
                 nic_params.push((true, "private-ip-address", &private_ip));
+                nic_params.push((true, "tags", &ws.global_config.tags));
 
                 // using global counter to make sure all public ip addresses are unique
                 let curr_pip_count = PUBLIC_IP_COUNT.fetch_add(1, Ordering::Relaxed);
-                let pip_name = &format!("public-ip-{}", curr_pip_count);
+                let pip_name = &crate::cloud_functions::azure::resource_name(&format!("public-ip-{}", curr_pip_count));
                 if nic.has_public_ip_address {
 
                     let tmp = generate_shell_command(&format!("\n{} network public-ip create", PROVIDERS_CONFIG["azure-cli-binary"]), 
                                                      vec![(true, "resource-group", &ws.global_config.resource_group),
-                                                          (true, "dns-name", &format!("{}-{}-{}", crate::utils::global_config::PROVIDERS_CONFIG["resource-group"].trim().to_ascii_lowercase(), crate::utils::global_config::NETWORK["dns_prefix"].trim().to_ascii_lowercase(), vm.name.trim().to_ascii_lowercase())),
-                                                          (true, "name", pip_name)]);
+                                                          (true, "dns-name", &format!("{}-{}-{}", crate::utils::global_config::PROVIDERS_CONFIG["resource-group"].trim().to_ascii_lowercase(), crate::utils::global_config::NETWORK["dns_prefix"].trim().to_ascii_lowercase(), vm.name.trim().to_ascii_lowercase())), //vm.name already contains the run ID
+                                                          (true, "name", pip_name),
+                                                          (true, "tags", &ws.global_config.tags)]);
 
                     nic_params.push((true, "public-ip-address", pip_name));
                     sh_script.push_str(&tmp);
@@ -117,17 +119,21 @@ fn generate_whole_vm_script(vm: &super::system::Vm, ws: &super::system::WholeSys
             sh_script.push_str(&nics_tmp_shell);
 
             // VM creation
-            let dns_name = format!("{}.taas", &vm.name);
+            let dns_name = format!("{}.taas", &vm.computer_name);
             let nics = public_nic_names + &private_nic_names; //creating this variable because of borrowing rules
             let vm_params: Vec<(bool, &str, &str)> = vec![(true, "resource-group", &ws.global_config.resource_group),
                                                     (true, "name", &vm.name),
+                                                    (true, "computer-name", &vm.computer_name),
                                                     (true, "nics", &nics),
                                                     (true, "image", &vm.image),
                                                     (true, "size", &vm.size),
                                                     (true, "admin-username", &vm.admin_username),
                                                     (true, "admin-password", &vm.admin_password),
                                                     (true, "authentication-type", &vm.authentication_type),
+                                                    (true, "ssh-key-values", &vm.ssh_key_values),
+                                                    (true, "custom-data", &vm.custom_data),
                                                     (true, "public-ip-address-dns-name", &dns_name),
+                                                    (true, "tags", &ws.global_config.tags),
                                                 ];
 
             let tmp = generate_shell_command(&format!("\n{} vm create", PROVIDERS_CONFIG["azure-cli-binary"]), vm_params);
@@ -142,7 +148,8 @@ fn generate_vnet_script(ws: &super::system::WholeSystem) -> String {
     let tmp = generate_shell_command(&format!("\n{} network vnet create", PROVIDERS_CONFIG["azure-cli-binary"]),
                                         vec![(true, "resource-group", &ws.global_config.resource_group),
                                             (true, "name", &ws.vnet.name),
-                                            (true, "address-prefixes", &ws.vnet.address_prefixes.to_string())]);
+                                            (true, "address-prefixes", &ws.vnet.address_prefixes.to_string()),
+                                            (true, "tags", &ws.global_config.tags)]);
     ret.push_str(&tmp);
     ret
 }
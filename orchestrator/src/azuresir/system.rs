@@ -7,6 +7,8 @@ pub struct Globals {
     pub location: String,
     pub resource_group: String,
     pub has_stub_network: bool,
+    /// Tags (in Azure CLI 'key=value key2=value2' format) put on every resource that supports them
+    pub tags: String,
 }
 
 #[derive(Debug)]
@@ -49,12 +51,15 @@ impl Nic {
 #[derive(Debug)]
 pub struct Vm {
     pub name: String,
+    pub computer_name: String, //hostname inside the VM, without the run ID prefix of the Azure name
     pub nics: Vec<Nic>,
     pub image: String, //azure-provided OSes
     pub size: String, //Azure virtual hardware config
     pub admin_username: String,
     pub admin_password: String,
     pub authentication_type: String,
+    pub ssh_key_values: String, //public key file
+    pub custom_data: String, //cloud-init file run at provisioning
     pub custom_script: String,
 }
 
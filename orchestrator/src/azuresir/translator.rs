@@ -4,6 +4,7 @@ use std::net::Ipv4Addr;
 use crate::utils::global_config;
 use crate::asir::Os;
 use crate::cloud_functions::azure::find_best_matching_vm_name;
+use crate::cloud_functions::azure::resource_name;
 use crate::cloud_functions::VmHardwareProperties;
 use std::sync::atomic::{AtomicU8, Ordering};
 
@@ -27,6 +28,8 @@ pub fn pasir_to_azuresir(vms: &Vec<pasir::machines::Vm>,
     //  the azure subnet
     //  all the attached NICs
 
+    // every Azure name is prefixed with the run ID, so that concurrent runs do not clash
+    let vnet_name = &resource_name(vnet_name);
     let vnet = super::system::Vnet{name: vnet_name.to_string(), address_prefixes:  CidrIP {ip: Ipv4Addr::new(10,0,0,0), netmask: 8}  };
 
     let mut subnet_idx = 0;
@@ -42,7 +45,7 @@ pub fn pasir_to_azuresir(vms: &Vec<pasir::machines::Vm>,
             let nic_num = all_nics[*vm_idx].len();
             all_nics[*vm_idx].push(super::system::Nic {
                 name: format!("{}-nic{}",
-                vms[*vm_idx].name.clone(), nic_num),
+                resource_name(&vms[*vm_idx].name), nic_num),
                 vnet: vnet.name.clone(),
                 subnet: subnet_name.clone(),
                 private_ip_address: lasir_subnet.connected_vms[vm_idx],
@@ -67,7 +70,7 @@ pub fn pasir_to_azuresir(vms: &Vec<pasir::machines::Vm>,
         // if the VM has no connections, as chosen by the user
         let vm_nics = if all_nics[vm_idx].is_empty() {
             let curr_ip_addr_count = IP_ADDR_COUNTER.fetch_add(1, Ordering::Relaxed);
-            let stub_nic_name: String = format!("{}-stub-nic-{}", pasir_vm.name, curr_ip_addr_count);
+            let stub_nic_name: String = format!("{}-stub-nic-{}", resource_name(&pasir_vm.name), curr_ip_addr_count);
             //TODO: remove hard-coded name and IP
             vec![super::system::Nic::new_with_public_ip(stub_nic_name,
                                                         format!("{}", vnet_name),
@@ -78,16 +81,19 @@ pub fn pasir_to_azuresir(vms: &Vec<pasir::machines::Vm>,
         };
         let vm_size = find_most_fitting_vm(pasir_vm);
         all_vms.push( super::system::Vm {
-            name: format!("{}", pasir_vm.name),
+            name: resource_name(&pasir_vm.name),
+            computer_name: pasir_vm.name.clone(),
             nics: vm_nics, //TODO: should use a better system than just cloning the vec
             image: global_config::COMMON_OS_MAP[&pasir_vm.os.get_common()].clone(),
             size: vm_size.to_string(),
             admin_username: pasir_vm.auth.user.to_string(),
             admin_password: pasir_vm.auth.password.to_string(),
             authentication_type: "all".to_string(),
+            ssh_key_values: crate::utils::ssh_key::public_key(&pasir_vm.auth.ssh_key()),
+            custom_data: crate::utils::ssh_key::sudo_cloud_init(&pasir_vm.auth.user),
             custom_script: {
                     if pasir_vm.config_template != "" {
-                        format!("test-deployment/{}/script.sh", pasir_vm.name)
+                        crate::state::run_path(&format!("{}/{}/script.sh", crate::paths::TEST_DEPLOYMENT_DIR, pasir_vm.name))
                     }
                     else {
                         "".to_string()
@@ -101,6 +107,7 @@ pub fn pasir_to_azuresir(vms: &Vec<pasir::machines::Vm>,
         location: "westeurope".to_string(),
         resource_group: rg,
         has_stub_network: true,
+        tags: crate::cloud_functions::azure::resource_tags(),
     };
     let ret = super::system::WholeSystem{
         global_config: gc,
@@ -12,6 +12,7 @@ use crate::shell_tools;
 use crate::shell_tools::RunInfo;
 use std::sync::Mutex;
 use std::net::Ipv4Addr;
+use crate::orchestrator::Shared;
 
 macro_rules! get_prop {
     ($map: ident, $name: expr, $default: expr) => {
@@ -163,18 +164,152 @@ const DELETE_RG: &str = "{
 }
 ";
 
+/// Name of an Azure resource for the current run. All resources are prefixed with the run ID
+/// so that multiple runs can share the same resource group.
+pub fn resource_name(name: &str) -> String {
+    format!("{}-{}", crate::state::run_id(), name)
+}
+
 // Get Azure public IP based on VM name
 pub fn get_public_ip(vm_name: &str) -> String {
 
     let ha = Command::new("sh")
             .arg("-c")
-            .arg(format!("{} vm show -d -g {} -n {} --query publicIps -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], vm_name)).output().unwrap().stdout;
+            .arg(format!("{} vm show -d -g {} -n {} --query publicIps -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], resource_name(vm_name))).output().unwrap().stdout;
 
     let ha = String::from_utf8_lossy(&ha);
     let ha = ha.trim();
     ha.to_string()
 }
 
+/// Returns true if a VM with the given name already exists in the resource group
+pub fn vm_exists(vm_name: &str) -> bool {
+    !shell_tools::run_command_no_output(&format!("{} vm show -g {} -n {}", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], resource_name(vm_name)), &SHELL.shell).failure()
+}
+
+/// Tags put on every resource created by TaaS, used to find them again for the teardown
+pub const TAG_RUN_ID: &str = "taas-run-id";
+pub const TAG_EXPIRY: &str = "taas-expiry";
+
+/// Azure CLI `--tags` argument for the resources of the current run
+pub fn resource_tags() -> String {
+    let mut ret = format!("{}={}", TAG_RUN_ID, crate::state::run_id());
+    if let Some(expiry) = crate::state::expires_at() {
+        ret.push_str(&format!(" {}={}", TAG_EXPIRY, expiry));
+    }
+    ret
+}
+
+/// Lists the IDs of all resources tagged with the given run ID. OS disks are not tagged by
+/// Azure when a VM is created, so they are found through the VMs instead.
+fn run_resource_ids(run_id: &str) -> Vec<String> {
+    let res = shell_tools::run_command(&format!("{} resource list -g '{}' --tag {}={} --query '[].id' -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], TAG_RUN_ID, run_id), &SHELL.shell);
+    res.panic_on_failure();
+    let mut ids: Vec<String> = res.stdout().lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect();
+
+    let vm_ids: Vec<String> = ids.iter().filter(|id| id.contains("/Microsoft.Compute/virtualMachines/")).cloned().collect();
+    for vm_id in vm_ids {
+        let disk = shell_tools::run_command(&format!("{} vm show --ids '{}' --query storageProfile.osDisk.managedDisk.id -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], vm_id), &SHELL.shell);
+        if disk.check_success() && disk.stdout().trim() != "" {
+            ids.push(disk.stdout().trim().to_string());
+        }
+    }
+    ids
+}
+
+/// Orders resource IDs so that resources are deleted before the ones they depend on (VMs, then disks, NICs, IPs, networks)
+fn deletion_order(id: &str) -> usize {
+    let order = ["/virtualMachines/", "/disks/", "/networkInterfaces/", "/publicIPAddresses/", "/virtualNetworks/"];
+    order.iter().position(|o| id.contains(o)).unwrap_or(order.len())
+}
+
+/// Deletes every resource that belongs to the given run, leaving the rest of the resource group untouched
+pub fn delete_run_resources(run_id: &str) {
+    let mut ids = run_resource_ids(run_id);
+    if ids.is_empty() {
+        println!("No resources found for run {}", run_id);
+        return;
+    }
+    ids.sort_by_key(|id| deletion_order(id));
+    shell_tools::run_command(&format!("{} resource delete --ids {}", PROVIDERS_CONFIG["azure-cli-binary"], ids.join(" ")), &SHELL.shell).panic_on_failure();
+}
+
+/// Public and private IP addresses of the shared orchestrator VM, None if it does not exist
+pub fn shared_vm_ips(shared: &Shared) -> Option<(String, String)> {
+    let res = shell_tools::run_command(&format!("{} vm show -d -g {} -n {} --query '[publicIps, privateIps]' -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], shared.resource_group, shared.name), &SHELL.shell);
+    if !res.check_success() {
+        return None;
+    }
+    let mut ips = res.stdout().split_whitespace().map(|ip| ip.to_string());
+    match (ips.next(), ips.next()) {
+        (Some(public), Some(private)) => Some((public, private)),
+        _ => None,
+    }
+}
+
+/// Creates the shared orchestrator VM in its own resource group, with a network that the networks of the runs can be peered with.
+/// Nothing there is tagged with a run ID, so that no teardown ever deletes it.
+pub fn create_shared_orchestrator(shared: &Shared, size: &str, user: &str, password: &str) {
+    let az = &PROVIDERS_CONFIG["azure-cli-binary"];
+    shell_tools::run_command(&format!("{} group create -n {} -l {}", az, shared.resource_group, PROVIDERS_CONFIG["location"]), &SHELL.shell).panic_on_failure();
+    shell_tools::run_command(&format!("{} network vnet create -g {} -n {} --address-prefixes {} --subnet-name default --subnet-prefixes {}",
+        az, shared.resource_group, shared.vnet(), crate::orchestrator::SHARED_ADDRESS_SPACE, crate::orchestrator::SHARED_ADDRESS_SPACE), &SHELL.shell).panic_on_failure();
+    let ssh_public_key = crate::utils::ssh_key::public_key(&shared.ssh_key);
+    let sudo_cloud_init = crate::utils::ssh_key::sudo_cloud_init(user);
+    shell_tools::run_command(&format!("{} vm create -g {} -n {} --image {} --size {} --admin-username {} --admin-password {} --ssh-key-values {} --custom-data {} --authentication-type all --vnet-name {} --subnet default --private-ip-address {}",
+        az, shared.resource_group, shared.name, crate::utils::global_config::COMMON_OS_MAP["UbuntuLTS"], size, user, password, ssh_public_key, sudo_cloud_init, shared.vnet(), crate::orchestrator::SHARED_PRIVATE_IP), &SHELL.shell).panic_on_failure();
+}
+
+fn vnet_id(resource_group: &str, vnet: &str) -> String {
+    let res = shell_tools::run_command(&format!("{} network vnet show -g {} -n {} --query id -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], resource_group, vnet), &SHELL.shell);
+    res.panic_on_failure();
+    res.stdout().trim().to_string()
+}
+
+/// Name of the peering on the side of the shared orchestrator, found again from the run ID at teardown
+fn shared_peering_name(run_id: &str) -> String {
+    format!("{}-peering", run_id)
+}
+
+/// Peers the network of a system of the current run with the one of the shared orchestrator, in both directions.
+/// The peering on the side of the run goes away with its network, the other one with delete_shared_peering().
+pub fn peer_with_shared(shared: &Shared, system_name: &str) {
+    let az = &PROVIDERS_CONFIG["azure-cli-binary"];
+    let run_vnet = resource_name(system_name);
+    let run_vnet_id = vnet_id(&PROVIDERS_CONFIG["resource-group"], &run_vnet);
+    let shared_vnet_id = vnet_id(&shared.resource_group, &shared.vnet());
+    shell_tools::run_command(&format!("{} network vnet peering create -g {} --vnet-name {} -n {} --remote-vnet {} --allow-vnet-access",
+        az, PROVIDERS_CONFIG["resource-group"], run_vnet, resource_name("shared-peering"), shared_vnet_id), &SHELL.shell).panic_on_failure();
+    shell_tools::run_command(&format!("{} network vnet peering create -g {} --vnet-name {} -n {} --remote-vnet {} --allow-vnet-access",
+        az, shared.resource_group, shared.vnet(), shared_peering_name(&crate::state::run_id()), run_vnet_id), &SHELL.shell).panic_on_failure();
+}
+
+/// Deletes the peering of the shared orchestrator network with the one of the run, if there is one
+pub fn delete_shared_peering(shared: &Shared, run_id: &str) {
+    shell_tools::run_command_no_output(&format!("{} network vnet peering delete -g {} --vnet-name {} -n {}",
+        PROVIDERS_CONFIG["azure-cli-binary"], shared.resource_group, shared.vnet(), shared_peering_name(run_id)), &SHELL.shell);
+}
+
+/// Returns the run IDs of all TaaS resources whose expiry tag is older than the given date.
+/// Resources without TaaS tags are never considered.
+pub fn expired_run_ids(now: &chrono::DateTime<chrono::Utc>) -> Vec<String> {
+    let res = shell_tools::run_command(&format!("{} resource list -g '{}' --tag {} --query '[].[tags.\"{}\", tags.\"{}\"]' -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], TAG_RUN_ID, TAG_RUN_ID, TAG_EXPIRY), &SHELL.shell);
+    res.panic_on_failure();
+
+    let mut ret: Vec<String> = Vec::new();
+    for line in res.stdout().lines() {
+        let fields: Vec<&str> = line.split('\t').map(|f| f.trim()).collect();
+        if fields.len() != 2 || fields[0].is_empty() {
+            continue;
+        }
+        let expired = chrono::DateTime::parse_from_rfc3339(fields[1]).map(|exp| exp < *now).unwrap_or(false);
+        if expired && !ret.contains(&fields[0].to_string()) {
+            ret.push(fields[0].to_string());
+        }
+    }
+    ret
+}
+
 /// Clears an entire resource group (i.e. makes it empty)
 /// Note: the resource group will NOT be deleted
 const CLEAR_FN: &str = "removeall.json";
@@ -204,15 +339,26 @@ pub fn clear_resource_group_v2(rg_name: &str) {
 #[allow(unused)]
 pub fn send_and_exec_script(machine_name: &str, script_text: &str) {
     let b64encoded = encode(script_text);
-    let json_fn = format!("{}-b64script.json", machine_name);
+    let json_fn = crate::state::run_path(&format!("{}-b64script.json", machine_name));
     fs::write(&json_fn , format!("{{\n\t\"script\": \"{}\"\n}}", &b64encoded));
 
-     shell_tools::run_command(&format!("{} vm extension set --resource-group {} --vm-name {} --name customScript --publisher Microsoft.Azure.Extensions --settings ./{}", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], machine_name, json_fn), &SHELL.shell).panic_on_failure();
+     shell_tools::run_command(&format!("{} vm extension set --resource-group {} --vm-name {} --name customScript --publisher Microsoft.Azure.Extensions --settings ./{}", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], resource_name(machine_name), json_fn), &SHELL.shell).panic_on_failure();
 }
 
-pub fn send_and_exec_script_small(machine_name: &str, script_text: &str) {
+/// Runs a script on the VM and returns what Azure reports back, i.e. the script's stdout and stderr
+/// (truncated by Azure to the last 4KB of each). The script is base64-encoded so that it can contain any quote.
+pub fn send_and_exec_script_output(machine_name: &str, script_text: &str) -> String {
+
+    let res = shell_tools::run_command(&format!("{} vm run-command invoke -g {} -n {} --command-id RunShellScript --scripts 'echo {} | base64 -d | sh' --query 'value[0].message' -o tsv", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], resource_name(machine_name), encode(script_text)), &SHELL.shell);
+    res.panic_on_failure();
+    res.stdout().to_string()
+}
 
-     shell_tools::run_command(&format!("{} vm run-command invoke -g {} -n {} --command-id RunShellScript --scripts '{}'", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], machine_name, script_text), &SHELL.shell).panic_on_failure();
+/// Restarts the VM, and only returns once it is able to run scripts again
+pub fn restart_vm(machine_name: &str) {
+    shell_tools::run_command(&format!("{} vm restart -g {} -n {}", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], resource_name(machine_name)), &SHELL.shell).panic_on_failure();
+    shell_tools::run_command_try_again(&format!("{} vm run-command invoke -g {} -n {} --command-id RunShellScript --scripts 'true'", PROVIDERS_CONFIG["azure-cli-binary"], PROVIDERS_CONFIG["resource-group"], resource_name(machine_name)), &SHELL.shell, 12, Some(std::time::Duration::from_secs(10)))
+        .panic_on_failure();
 }
 
 fn check_logged_in() {
@@ -9,6 +9,7 @@
 
 use crate::asir;
 use crate::asir::Os as AsirOs;
+use std::collections::BTreeMap;
 use crate::lasir::connections::*;
 
 #[derive(Debug, Clone)]
@@ -79,14 +80,33 @@ pub struct Vm {
     pub override_config: Option<String>,
     pub config_template: String,
     pub has_remote_access: bool,
-    pub role: String,
+    pub roles: Vec<Role>,
     pub auth: Auth,
 }
 
+impl Vm {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r.name == role)
+    }
+
+    pub fn role(&self, role: &str) -> Option<&Role> {
+        self.roles.iter().find(|r| r.name == role)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Role {
+    pub name: String,
+    /// Attribute name -> value, e.g. port -> 5432
+    pub attributes: BTreeMap<String, String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Auth {
     pub user: String,
     pub password: String,
+    /// Private key of system.yml, None for the key pair of the run
+    pub ssh_key: Option<String>,
 }
 
 impl asir::Vm<Os> for Vm {
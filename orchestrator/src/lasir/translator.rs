@@ -6,6 +6,33 @@ use crate::lasir::connections::VmConnectionLogical;
 use crate::yamlsir::default;
 use crate::post_deployment;
 
+/// The roles of a machine, from `role` and `roles` (names only, or names with their attributes)
+fn machine_roles(machine: &yamlsir::Machine) -> Vec<super::machines::Role> {
+    let attribute_value = |role: &str, attribute: &str, value: &serde_yaml::Value| match value {
+        serde_yaml::Value::String(s) => s.clone(),
+        serde_yaml::Value::Number(n) => n.to_string(),
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        serde_yaml::Value::Null => String::new(),
+        _ => panic!("Error: attribute '{}' of role '{}' of machine {} must be a string, a number or a boolean", attribute, role, machine.name),
+    };
+
+    let mut ret: Vec<super::machines::Role> = machine.role.iter().map(|name| super::machines::Role { name: name.clone(), attributes: Default::default() }).collect();
+    match &machine.roles {
+        Some(yamlsir::Roles::Names(names)) => ret.extend(names.iter().map(|name| super::machines::Role { name: name.clone(), attributes: Default::default() })),
+        Some(yamlsir::Roles::WithAttributes(roles)) => ret.extend(roles.iter().map(|(name, attributes)| super::machines::Role {
+            name: name.clone(),
+            attributes: attributes.iter().flatten().map(|(attribute, value)| (attribute.clone(), attribute_value(name, attribute, value))).collect(),
+        })),
+        None => (),
+    }
+    for (idx, role) in ret.iter().enumerate() {
+        if ret[..idx].iter().any(|r| r.name == role.name) {
+            panic!("Error: machine {} has role '{}' more than once", machine.name, role.name);
+        }
+    }
+    ret
+}
+
 /**
  * @brief
  * transforms the near-raw YAMLSIR to LASIR, which contains a couple more
@@ -42,17 +69,25 @@ pub fn yamlsir_to_lasir(root: &yamlsir::Root) -> super::machines::LogicalSystem<
             None => None,
         };
 
-        let auth = super::machines::Auth {
-            user: machine.auth.username.clone(),
-            password: machine.auth.password.clone(),
+        let auth = match &machine.auth {
+            Some(auth) => super::machines::Auth {
+                user: auth.username.clone(),
+                password: auth.password.clone().unwrap_or_else(|| crate::state::password(vm_name)),
+                ssh_key: auth.ssh_key.as_ref().map(|key| {
+                    let key = crate::utils::ssh_key::expand_home(key);
+                    crate::utils::ssh_key::public_key(&key);
+                    key
+                }),
+            },
+            None => super::machines::Auth {
+                user: crate::utils::global_config::DEFAULT_VALUES.username.clone(),
+                password: crate::state::password(vm_name),
+                ssh_key: None,
+            },
         };
 
         // Add usernames and passwords of each VM in the global replacement map for post-deployement scripts
-        //TODO: do it more cleanly
-        post_deployment::add_global_replacement(&format!("machines/{}/user", &vm_name), &format!("{}", &auth.user));
-        post_deployment::add_global_replacement(&format!("machines/{}/username", &vm_name), &format!("{}", &auth.user));
-        post_deployment::add_global_replacement(&format!("machines/{}/pass", &vm_name), &format!("{}", &auth.password));
-        post_deployment::add_global_replacement(&format!("machines/{}/password", &vm_name), &format!("{}", &auth.password));
+        post_deployment::add_machine_credentials(vm_name, &auth.user, &auth.password);
 
         vm_indices.insert(vm_name.to_string(), lasir_vms.len());
 
@@ -64,7 +99,7 @@ pub fn yamlsir_to_lasir(root: &yamlsir::Root) -> super::machines::LogicalSystem<
             /*auth_type: auth,*/
             config_template: machine.config_template.to_string(),
             has_remote_access: machine.remote_access,
-            role: machine.role.clone(),
+            roles: machine_roles(machine),
             auth: auth,
         };
         lasir_vms.push(new_vm);
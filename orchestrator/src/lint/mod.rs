@@ -0,0 +1,137 @@
+// lint/mod.rs
+//
+// `orchestrator lint <scenario>` reports the replacement tokens of a scenario that would have no value, without deploying anything.
+// The replacements of every machine are computed as for a run, with placeholders for what is only known once the machines exist
+// (public hostnames, address of the webserver).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use crate::paths;
+use crate::pipelines::TaasPipeline;
+use crate::utils::replace;
+use crate::utils::run_include;
+use crate::utils::run_parser::{self, StepType};
+
+const PLACEHOLDER_WS_HOST: &str = "10.1.0.5";
+const PLACEHOLDER_DOMAIN: &str = "example.com";
+
+/// A token without replacement, found in a file of the scenario
+struct Unresolved {
+    file: String,
+    line: Option<usize>,
+    token: String,
+    /// Machines lacking the token, empty for the scripts run on the operator's host
+    machines: Vec<String>,
+}
+
+/// Lints the scenario and exits with code 1 if any token has no replacement
+pub fn run<P: TaasPipeline>(scenario: &str) {
+    match check::<P>(scenario) {
+        0 => println!("No unresolved replacement token in scenario {}.", scenario),
+        count => {
+            println!("{} unresolved replacement token(s) in scenario {}.", count, scenario);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Prints every token of the templates, pipeline and post-deployment script of the scenario that has no replacement,
+/// and returns how many there are
+pub fn check<P: TaasPipeline + ?Sized>(scenario: &str) -> usize {
+    let yamlsir_root = P::parse_yaml(scenario);
+    let lasir_system = P::yamlsir_to_lasir(&yamlsir_root);
+    let (pasir_vms, pasir_subnet) = P::lasir_to_pasir(&lasir_system);
+
+    let public_hosts: HashMap<String, String> = pasir_vms.iter().filter(|vm| vm.has_remote_access)
+        .map(|vm| (vm.name.clone(), format!("{}.{}", vm.name, PLACEHOLDER_DOMAIN)))
+        .collect();
+    for (name, host) in &public_hosts {
+        crate::post_deployment::add_machine_public_host(name, host);
+    }
+    let (orch_user, orch_pass) = crate::orchestrator::credentials();
+    crate::post_deployment::add_machine_credentials("orchestrator", orch_user, &orch_pass);
+    let global_map = crate::post_deployment::global_replacements();
+
+    let run_map = P::run_replacement_map(Some(PLACEHOLDER_WS_HOST), &public_hosts);
+    let machine_maps: HashMap<String, HashMap<String, String>> = pasir_vms.iter()
+        .zip(P::vm_replacement_maps(&lasir_system, &pasir_vms, &pasir_subnet))
+        .map(|(vm, vm_map)| {
+            let mut map = run_map.clone();
+            map.extend(vm_map);
+            (vm.name.clone(), map)
+        })
+        .collect();
+
+    let mut found = Vec::new();
+
+    let templates_dir = format!("{}/{}/{}", paths::SCENARIO_PATH, scenario, paths::DEPLOYMENT_TEMPLATES_PATH);
+    for vm in pasir_vms.iter().filter(|vm| !vm.config_template.is_empty()) {
+        for path in replace::files_in(Path::new(&format!("{}/{}/replace", templates_dir, vm.config_template))) {
+            // binary files are copied without replacement
+            if let Ok(text) = fs::read_to_string(&path) {
+                let file = path.display().to_string();
+                record(&mut found, &text, &[(file, text.clone())], &machine_maps[&vm.name], Some(&vm.name));
+            }
+        }
+    }
+
+    let pipeline_fn = format!("{}/{}/{}", paths::SCENARIO_PATH, scenario, paths::RUN_STEPS_FN);
+    if Path::new(&pipeline_fn).exists() {
+        let steps = run_parser::parse_run_list(&pipeline_fn, &pasir_vms);
+        // the steps come from pipeline.run and the files it includes
+        let sources: Vec<(String, String)> = run_include::source_files(&pipeline_fn).iter()
+            .map(|path| (path.display().to_string(), fs::read_to_string(path).unwrap_or_default()))
+            .collect();
+        for step in steps {
+            match step.step_type {
+                StepType::Run | StepType::Setup => for (machine, script) in &step.scripts {
+                    if let Some(map) = machine_maps.get(machine) {
+                        record(&mut found, script, &sources, map, Some(machine));
+                    }
+                },
+                StepType::Local => for script in step.scripts.values() {
+                    record(&mut found, script, &sources, &global_map, None);
+                },
+                _ => (),
+            }
+        }
+    }
+
+    let post_deployment_fn = format!("{}/{}/{}", paths::SCENARIO_PATH, scenario, paths::POST_DEPLOYMENT_SCRIPT_FN);
+    if let Ok(text) = fs::read_to_string(&post_deployment_fn) {
+        record(&mut found, &text, &[(post_deployment_fn.clone(), text.clone())], &global_map, None);
+    }
+
+    for unresolved in &found {
+        let location = match unresolved.line {
+            Some(line) => format!("{}:{}", unresolved.file, line),
+            None => unresolved.file.clone(),
+        };
+        match unresolved.machines.is_empty() {
+            true => println!("{}: ¥{{{}}} has no replacement", location, unresolved.token),
+            false => println!("{}: ¥{{{}}} has no replacement on {}", location, unresolved.token, unresolved.machines.join(", ")),
+        }
+    }
+    found.len()
+}
+
+/// Adds the unresolved tokens of a text, taken from the given (file name, content) sources, to the ones found so far
+fn record(found: &mut Vec<Unresolved>, text: &str, sources: &[(String, String)], map: &HashMap<String, String>, machine: Option<&str>) {
+    for token in replace::unresolved_tokens(text, map, Some(sources[0].0.clone())) {
+        let (file, line) = token_location(sources, &token);
+        match found.iter_mut().find(|u| u.file == file && u.line == line && u.token == token) {
+            Some(unresolved) => unresolved.machines.extend(machine.map(|m| m.to_string())),
+            None => found.push(Unresolved { file, line, token, machines: machine.map(|m| m.to_string()).into_iter().collect() }),
+        }
+    }
+}
+
+/// File and line (from 1) of the first occurrence of the token in the sources, or the first source without a line
+/// if it cannot be found
+fn token_location(sources: &[(String, String)], token: &str) -> (String, Option<usize>) {
+    let full_token = format!("¥{{{}}}", token);
+    let find = |pattern: &str| sources.iter()
+        .find_map(|(file, source)| source.lines().position(|line| line.contains(pattern)).map(|idx| (file.clone(), Some(idx + 1))));
+    find(&full_token).or_else(|| find(token)).unwrap_or_else(|| (sources[0].0.clone(), None))
+}
@@ -24,6 +24,11 @@ mod shell_tools;
 #[macro_use]
 mod post_deployment;
 mod script_push;
+mod state;
+mod teardown;
+mod report;
+mod serve;
+mod lint;
 
 // ============ YAMLSIR ==============
 mod yamlsir;
@@ -41,7 +46,6 @@ use pipelines::TaasPipeline;
 
 use std::env;
 use utils::global_config::PROVIDERS_CONFIG;
-use utils::global_config::SHELL;
 use script_push::ScriptPush;
 
 fn main() {
@@ -50,9 +54,14 @@ fn main() {
     match args.get(1) {
         Some(arg) => {
             match arg.to_lowercase().as_ref() {
-                "delete" | "remove" | "clean" | "rm" | "del" => { clean_azure(); }
+                "delete" | "remove" | "clean" | "rm" | "del" => { clean_azure(args.get(2)); }
                 "push" => { azure_push_script(&args[2..]); }
-                _ => { normal_azure(arg); }
+                "resume" => { resume_azure(args.get(2)); }
+                "reap" => { reap_azure(); }
+                "compare" => { compare_runs(&args[2..]); }
+                "serve" => { serve::run(&args[2..]); }
+                "lint" => { lint_scenario(args.get(2)); }
+                _ => { normal_azure(arg, parse_run_options(&args[2..])); }
             }
         }
         None => {
@@ -62,26 +71,113 @@ fn main() {
     }
 }
 
-fn normal_azure(scenario: &str) {
+/// Parses the options that can follow the scenario name, e.g. `orchestrator my_scenario --run-id ci-42 --repeat 10`
+fn parse_run_options(args: &[String]) -> state::RunOptions {
+    let mut options = state::RunOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--run-id" => options.run_id = Some(args.next().expect("Error: --run-id requires a value").to_string()),
+            "--repeat" => options.repeat = Some(args.next().and_then(|n| n.parse().ok()).filter(|n| *n > 0).expect("Error: --repeat requires a positive number")),
+            "--baseline" => {
+                let baseline = args.next().expect("Error: --baseline requires a run ID");
+                // fail before deploying anything if the baseline cannot be compared with
+                report::load(baseline);
+                options.baseline = Some(baseline.to_string());
+            },
+            "--threshold" => options.threshold_percent = Some(parse_threshold(args.next())),
+            "--strict" => options.strict = true,
+            "--seed" => options.seed = Some(args.next().and_then(|n| n.parse().ok()).expect("Error: --seed requires a number")),
+            _ => panic!("Error: unknown option '{}'", arg),
+        }
+    }
+    options
+}
+
+fn parse_threshold(arg: Option<&String>) -> f64 {
+    arg.and_then(|t| t.trim_end_matches('%').parse().ok()).filter(|t: &f64| *t >= 0.0).expect("Error: --threshold requires a positive percentage")
+}
+
+/// Compares the report of a run with the one of a baseline run: `orchestrator compare <baseline run> <run> [--threshold 10]`.
+/// Exits with code 1 if there is any regression.
+fn compare_runs(args: &[String]) {
+    let mut runs = Vec::new();
+    let mut threshold = utils::global_config::DEFAULT_VALUES.regression_threshold_percent;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--threshold" => threshold = parse_threshold(args.next()),
+            _ if arg.starts_with("--") => panic!("Error: unknown option '{}'", arg),
+            _ => runs.push(arg),
+        }
+    }
+    if runs.len() != 2 {
+        panic!("Error: compare requires two run IDs: orchestrator compare <baseline run> <run> [--threshold <percent>]");
+    }
+
+    let regressions = report::compare::compare(&report::load(runs[0]), &report::load(runs[1]), threshold);
+    std::process::exit(if regressions > 0 { 1 } else { 0 });
+}
+
+/// Reports the replacement tokens of a scenario that have no value, without deploying it
+fn lint_scenario(scenario: Option<&String>) {
+    let scenario = scenario.expect("Error: please provide the name of the scenario to lint");
+    lint::run::<pipelines::azure_cli::AzureTaasPipeline>(scenario);
+}
+
+fn normal_azure(scenario: &str, options: state::RunOptions) {
     cloud_functions::azure::check_azure_cli_install();
-    //remove temp folders from potential previous run
-    shell_tools::run_command_no_output(&format!("rm -rf {}", "temp_common_data temp-template-deployment test-deployment temp_deploy_scripts *-b64script.json create_orchestrator.sh"), &SHELL.shell);
     // Build the VM network, deploy it, run tests
     // azure_pipeline(scenario);
-    pipelines::azure_cli::AzureTaasPipeline::run_v2(scenario);
+    pipelines::azure_cli::AzureTaasPipeline::run_v2(scenario, options);
+}
+
+fn resume_azure(run_id: Option<&String>) {
+    if let Some(run_id) = run_id {
+        state::check_run_id(run_id);
+    }
+    cloud_functions::azure::check_azure_cli_install();
+    // the run directory is kept on purpose, it still holds the data of the interrupted run
+    pipelines::azure_cli::AzureTaasPipeline::resume(run_id.map(|id| id.as_ref()));
 }
 
-fn clean_azure() {
+fn reap_azure() {
+    cloud_functions::azure::check_azure_cli_install();
+    teardown::reap();
+}
+
+/// Deletes the resources and files of a single run (the last one if no run ID is given),
+/// or of everything in the resource group with `--all`
+fn clean_azure(target: Option<&String>) {
 
     cloud_functions::azure::check_azure_cli_install();
 
-    println!("Clearing temporary files...");
-    shell_tools::run_command_no_output(&format!("rm -rf {}", "last_deployment_replacements.yml last_deployment_summary.yml temp_common_data temp-template-deployment test-deployment temp_deploy_scripts *-b64script.json create_orchestrator.sh"), &SHELL.shell);
+    match target.map(|t| t.as_ref()) {
+        Some("--all") => {
+            println!("Clearing temporary files...");
+            remove_dir_if_exists(paths::RUNS_PATH);
+
+            // Clear the Azure resource group
+            // TODO: move it in azure pipeline
+            println!("Clearing resource group {}\nThis may take a while...", PROVIDERS_CONFIG["resource-group"]);
+            cloud_functions::azure::clear_resource_group_v2(&PROVIDERS_CONFIG["resource-group"]);
+        },
+        target => {
+            // the ID ends up in a path and in Azure queries, so nothing is deleted before it is checked
+            let run_id = state::check_run_id(&target.map(|t| t.to_string()).unwrap_or_else(state::last_run_id));
+            println!("Deleting resources of run {}\nThis may take a while...", run_id);
+            teardown::delete_run(&run_id);
+
+            println!("Clearing temporary files...");
+            remove_dir_if_exists(&format!("{}/{}", paths::RUNS_PATH, run_id));
+        },
+    }
+}
 
-    // Clear the Azure resource group
-    // TODO: move it in azure pipeline
-    println!("Clearing resource group {}\nThis may take a while...", PROVIDERS_CONFIG["resource-group"]);
-    cloud_functions::azure::clear_resource_group_v2(&PROVIDERS_CONFIG["resource-group"]);
+fn remove_dir_if_exists(dir: &str) {
+    if std::path::Path::new(dir).exists() {
+        std::fs::remove_dir_all(dir).unwrap_or_else(|_| panic!("Error: could not delete {}. Please check permissions", dir));
+    }
 }
 
 fn azure_push_script(args: &[String]) {
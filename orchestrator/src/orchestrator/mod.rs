@@ -4,44 +4,165 @@ use crate::utils::types::CidrIP;
 use std::collections::HashMap;
 use crate::utils::global_config::WS_CONFIG;
 use crate::utils::global_config::CLOUD_PROVIDER;
+use crate::utils::global_config::SHELL;
+use crate::shell_tools;
+use crate::shell_tools::RunInfo;
+use serde::{Serialize, Deserialize};
 use std::env;
 
 const ORCH_USER: &str = "orch";
-const ORCH_PASS: &str = "asdfgDDFjklqwe1234";
+const ORCH_NAME: &str = "orchestrator";
+/// Where the machines keep their webserver token, for the helpers they run themselves (e.g. taas_barrier.sh)
+pub const MACHINE_TOKEN_FN: &str = "/etc/taas_token";
+/// The tokens file of the webserver, on the orchestrator VM
+pub const WEBSERVER_TOKENS_FN: &str = ".taas_tokens";
+/// Port of the webserver
+pub const WEBSERVER_PORT: u16 = 8000;
+/// Folder holding the folder of every run on a shared orchestrator, under the home of its user
+pub const SHARED_RUNS_DIR: &str = "runs";
+/// Folder of a shared orchestrator where the common data of all runs is kept, so that only what changed gets uploaded
+pub const SHARED_COMMON_DATA_CACHE: &str = "common_data_cache";
+/// Network of a shared orchestrator, outside of the 10.0.0.0/8 of the runs so that their networks can be peered with it
+pub const SHARED_ADDRESS_SPACE: &str = "172.31.0.0/24";
+pub const SHARED_PRIVATE_IP: &str = "172.31.0.4";
+/// Size of a shared orchestrator VM without override_vm
+pub const SHARED_DEFAULT_SIZE: &str = "Standard_B1s";
+/// Private key of a shared orchestrator without `ssh_key`
+pub const SHARED_DEFAULT_SSH_KEY: &str = "~/.ssh/id_rsa";
 
-/// Creates a hard-coded PASIR VM for the orchestrator (webserver)
-/// It runs ubuntu and has its own, independent network
-pub fn create() -> (pasir::machines::Vm, pasir::connections::Subnet) {
+/// Long-lived orchestrator VM kept in its own resource group and reused across runs,
+/// configured with the `shared` section of config/webserver.yml
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Shared {
+    pub resource_group: String,
+    pub name: String,
+    /// Password of its user, which cannot be generated for a run as the VM outlives them
+    #[serde(default)]
+    pub password: String,
+    /// Private key to log in with, next to its .pub, for the same reason
+    #[serde(default = "shared_ssh_key")]
+    pub ssh_key: String,
+}
+
+fn shared_ssh_key() -> String {
+    crate::utils::ssh_key::expand_home(SHARED_DEFAULT_SSH_KEY)
+}
+
+impl Shared {
+    pub fn vnet(&self) -> String {
+        format!("{}-vnet", self.name)
+    }
+}
+
+/// The shared orchestrator of config/webserver.yml, if there is one
+pub fn shared_config() -> Option<Shared> {
+    let shared = WS_CONFIG.as_ref()?.get("shared")?;
+    let get = |key: &str| shared.get(key).cloned().unwrap_or_else(|| panic!("Error: the shared section of {} needs a '{}'", crate::paths::WS_CONFIG, key));
+    let ssh_key = shared.get("ssh_key").map(|key| crate::utils::ssh_key::expand_home(key)).unwrap_or_else(shared_ssh_key);
+    Some(Shared { resource_group: get("resource_group"), name: get("name"), password: get("password"), ssh_key })
+}
 
+/// Where the files of the current run are on the orchestrator VM, relative to the home of its user and to the webserver root:
+/// at the top for a dedicated orchestrator, in runs/<run id>/ for a shared one
+pub fn remote_path(path: &str) -> String {
+    match crate::state::shared_orchestrator() {
+        Some(_) => format!("{}/{}/{}", SHARED_RUNS_DIR, crate::state::run_id(), path),
+        None => path.to_string(),
+    }
+}
+
+/// URL of the files of the current run on the webserver, without trailing slash
+pub fn base_url(host: &str) -> String {
+    match crate::state::shared_orchestrator() {
+        Some(_) => format!("http://{}:{}/{}/{}", host, WEBSERVER_PORT, SHARED_RUNS_DIR, crate::state::run_id()),
+        None => format!("http://{}:{}", host, WEBSERVER_PORT),
+    }
+}
+
+/// True if the webserver service runs on the orchestrator VM
+pub fn is_healthy(ip: &str) -> bool {
+    !shell_tools::run_command_no_output(&format!("ssh {} -oConnectTimeout=20 {}@{} \"systemctl is-active --quiet orche\"", ssh_args(), ORCH_USER, ip), &SHELL.shell).failure()
+}
+
+/// Options of the download tool (curl or wget) that authenticate a machine to the webserver
+pub fn auth_args(token: &str) -> String {
+    match SHELL.download_tool.as_ref() {
+        "curl" => format!("-H 'Authorization: Bearer {}'", token),
+        _ => format!("--header='Authorization: Bearer {}'", token),
+    }
+}
+
+/// Removes what a run left on the shared orchestrator of config/webserver.yml (its folder, and the peering with its network), if there is one
+pub fn release_shared(run_id: &str) {
+    let shared = match shared_config() {
+        Some(shared) => shared,
+        None => return,
+    };
+    // the ID ends up in an rm -rf on the shared orchestrator
+    crate::state::check_run_id(run_id);
+    crate::cloud_functions::azure::delete_shared_peering(&shared, run_id);
+    if let Some((ip, _)) = crate::cloud_functions::azure::shared_vm_ips(&shared) {
+        shell_tools::run_command_no_output(&format!("ssh {} {}@{} \"rm -rf {}/{}\"", crate::utils::ssh_key::ssh_args(&shared.ssh_key), ORCH_USER, ip, SHARED_RUNS_DIR, run_id), &SHELL.shell);
+    }
+}
+
+/// Size of the orchestrator VM, from the ORCH_SIZE environment variable or config/webserver.yml
+pub fn override_size() -> Option<String> {
     // Extra override for Environment Variables
     // TODO: offer this feature for all configuration variables in a separate module
-    let override_config = match env::var_os("ORCH_SIZE") {
-        Some(val) => serde::export::Some(val.into_string().unwrap()),
+    match env::var_os("ORCH_SIZE") {
+        Some(val) => Some(val.into_string().unwrap()),
         None => WS_CONFIG.clone().and_then(|wsc| wsc.get("override_vm").and_then(|wsc| wsc.get(&CLOUD_PROVIDER.to_string()).map(|b| b.clone())))
-    };
+    }
+}
+
+/// User name and password of the orchestrator VM: generated for the run, or the ones of config/webserver.yml for a shared orchestrator
+pub fn credentials() -> (&'static str, String) {
+    match crate::state::shared_orchestrator() {
+        Some(shared) => (ORCH_USER, shared.password),
+        None => (ORCH_USER, crate::state::password(ORCH_NAME)),
+    }
+}
+
+/// Private key of the orchestrator VM: the key pair of the run, or the one of config/webserver.yml for a shared orchestrator
+pub fn ssh_key() -> String {
+    match crate::state::shared_orchestrator() {
+        Some(shared) => shared.ssh_key,
+        None => crate::utils::ssh_key::run_key(),
+    }
+}
+
+/// Options of ssh and scp (without the user and host) to log in to the orchestrator VM
+pub fn ssh_args() -> String {
+    crate::utils::ssh_key::ssh_args(&ssh_key())
+}
+
+/// Creates a hard-coded PASIR VM for the orchestrator (webserver)
+/// It runs ubuntu and has its own, independent network
+pub fn create() -> (pasir::machines::Vm, pasir::connections::Subnet) {
+
+    let override_config = override_size();
+    let (user, password) = credentials();
 
     let vm = pasir::machines::Vm {
-        name: "orchestrator".to_string(),
+        name: ORCH_NAME.to_string(),
         os: pasir::machines::Os {
             candidates: asir::OsCandidates::common_only("UbuntuLTS"),
         },
         hwconfig: Some(pasir::machines::HwConfig::default()),
         override_config: override_config,
         config_template: "".to_string(),
-        role: "".to_string(),
+        roles: Vec::new(),
         has_remote_access: true,
         auth: pasir::machines::Auth {
-            user: ORCH_USER.to_string(),
-            password: ORCH_PASS.to_string(),
+            user: user.to_string(),
+            password: password.clone(),
+            ssh_key: None,
         },
     };
 
     // For the post-deployment glogabl replacement map
-    //TODO: do it more properly
-    crate::post_deployment::add_global_replacement("machines/orchestrator/user", ORCH_USER);
-    crate::post_deployment::add_global_replacement("machines/orchestrator/username", ORCH_USER);
-    crate::post_deployment::add_global_replacement("machines/orchestrator/pass", ORCH_PASS);
-    crate::post_deployment::add_global_replacement("machines/orchestrator/password", ORCH_PASS);
+    crate::post_deployment::add_machine_credentials(ORCH_NAME, user, &password);
 
     let sn = pasir::connections::Subnet {
         prefix: CidrIP::from("10.1.0.0/16"),
@@ -127,6 +127,9 @@ pub fn create_network_from_logical(connections: &dyn VmConnectionLogical) -> Vec
         
         debug_first_iter = false;
         let unreached_neighbours = check_missing_neighbours(vm_idx, &current_subnets, &all_neighbours);
+        // sorted so that the generated network is the same from one run to the next (required to resume a deployment)
+        let mut unreached_neighbours: Vec<usize> = unreached_neighbours.into_iter().collect();
+        unreached_neighbours.sort();
 
         for new_neighbour in &unreached_neighbours {
             current_subnets.push(SubnetCandidate {connected_vms: {let mut ret = HashSet::new(); ret.insert(vm_idx); ret.insert(*new_neighbour); ret}});
@@ -252,7 +255,9 @@ pub fn assign_subnets_and_ip_v2(logical_subnets: &Vec<SubnetCandidate>) -> Vec<S
         ip_counter = ip_counter + IP_ADDRESSES_RESERVED;
         
         let mut connected_vms: HashMap<usize, Ipv4Addr> = HashMap::new();
-        for vm in &l_subnet.connected_vms {
+        let mut ordered_vms: Vec<&usize> = l_subnet.connected_vms.iter().collect();
+        ordered_vms.sort();
+        for vm in ordered_vms {
             connected_vms.insert(*vm, ip_counter.to_Ipv4Addr());
             ip_counter = ip_counter + 1;
         }
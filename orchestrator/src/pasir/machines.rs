@@ -92,7 +92,8 @@ pub struct Vm {
     pub override_config: Option<String>,
     pub config_template: String,
     pub has_remote_access: bool,
-    pub role: String,
+    /// Names of the roles of the machine
+    pub roles: Vec<String>,
     pub auth: Auth,
 }
 
@@ -100,6 +101,15 @@ pub struct Vm {
 pub struct Auth {
     pub user: String,
     pub password: String,
+    /// Private key of system.yml, None for the key pair of the run
+    pub ssh_key: Option<String>,
+}
+
+impl Auth {
+    /// Private key to log in with
+    pub fn ssh_key(&self) -> String {
+        self.ssh_key.clone().unwrap_or_else(crate::utils::ssh_key::run_key)
+    }
 }
 
 impl asir::Vm<Os> for Vm {
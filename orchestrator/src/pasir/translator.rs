@@ -19,6 +19,7 @@ pub fn lasir_vms_to_pasir_vms<'a, I>(vms: I) -> Vec<super::machines::Vm>
         let auth = super::machines::Auth {
             user: vm.auth.user.clone(),
             password: vm.auth.password.clone(),
+            ssh_key: vm.auth.ssh_key.clone(),
         };
         super::machines::Vm {
             name: vm.name.clone(),
@@ -27,7 +28,7 @@ pub fn lasir_vms_to_pasir_vms<'a, I>(vms: I) -> Vec<super::machines::Vm>
             hwconfig: out_hwc,
             config_template: vm.config_template.clone(),
             has_remote_access: vm.has_remote_access,
-            role: vm.role.clone(),
+            roles: vm.roles.iter().map(|r| r.name.clone()).collect(),
             auth: auth,
         }
     }
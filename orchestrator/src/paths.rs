@@ -8,8 +8,32 @@ pub const WS_CONFIG: &str = "config/webserver.yml";
 pub const NETWORK_CONFIG: &str = "config/network.yml";
 pub const SSH_CONFIG: &str = "config/ssh.yml";
 pub const DEPLOYMENT_TEMPLATES_PATH: &str = "deployment_templates";
+// Optional, in a deployment template: files of the template to install at absolute paths on the machine
+pub const PLACEMENT_FN: &str = "placement.yml";
 pub const SCENARIO_PATH: &str = "scenarios";
 pub const SYSTEM_YAML_NAME: &str = "system.yml";
 pub const POST_DEPLOYMENT_SCRIPT_FN: &str = "post_deployment.sh";
 pub const SHELL_CONFIG: &str = "config/shell.yml";
-pub const RUN_STEPS_FN: &str = "pipeline.run";
\ No newline at end of file
+pub const RUN_STEPS_FN: &str = "pipeline.run";
+pub const COMMON_STEPS_PATH: &str = "scenarios/common_steps";
+// Everything generated by a run lives in RUNS_PATH/<run id>/
+pub const RUNS_PATH: &str = "runs";
+pub const LAST_RUN_FN: &str = "runs/last";
+pub const DEPLOYMENT_STATE_FN: &str = "state.yml";
+pub const RUN_REPORT_FN: &str = "run_report.yml";
+pub const DEPLOYMENT_SUMMARY_FN: &str = "deployment_summary.yml";
+pub const DEPLOYMENT_REPLACEMENTS_FN: &str = "deployment_replacements.yml";
+pub const TEST_DEPLOYMENT_DIR: &str = "test-deployment";
+pub const TEMPLATE_DEPLOYMENT_DIR: &str = "temp-template-deployment";
+pub const COMMON_DATA_DIR: &str = "temp_common_data";
+pub const DOWNLOADS_DIR: &str = "downloads";
+// File server of the orchestrator VM: a Linux build of this program, used instead of the running executable if present
+pub const WEBSERVER_BINARY_OVERRIDE: &str = "webserver/orchestrator-linux";
+// Barriers: machines call the helper, the orchestrator webserver runs the daemon
+pub const BARRIER_HELPER_TEMPLATE: &str = "webserver/taas_barrier.sh";
+pub const BARRIER_HELPER_FN: &str = "taas_barrier.sh";
+// Entries added to /etc/hosts on every machine, for the names of the machines and roles it is connected to
+pub const HOSTS_FRAGMENT_FN: &str = "taas_hosts";
+// SSH key pair of a run, in its run directory, and the cloud-init files giving passwordless sudo to the users of its machines
+pub const RUN_SSH_KEY_FN: &str = "ssh_key";
+pub const SUDO_CLOUD_INIT_PREFIX: &str = "cloud_init_sudo";
@@ -6,6 +6,7 @@ use crate::shell_tools;
 use shell_tools::RunInfo;
 use crate::pipelines::TaasPipeline;
 use crate::utils::global_config::SHELL;
+use crate::state;
 
 pub struct AzureTaasPipeline {}
 
@@ -19,17 +20,32 @@ impl TaasPipeline for AzureTaasPipeline {
         let azuresir = azuresir::translator::pasir_to_azuresir(&pasir_vms, pasir_network, system_name);
         let emitter_system = azuresir::emitter::emit_new(&azuresir);
         let mut network_script = String::with_capacity(emitter_system.network.len());// + emitter_system.vms.len() * emitter_system.vms[0].len());
-        println_with_time!("Azure - Creating network for {}", system_name);
-        network_script.push_str(&emitter_system.network);
-        crate::shell_tools::run_command(&format!("{}", network_script), &SHELL.shell).panic_on_failure();
+        let network_phase = state::Phase::Network(system_name.to_string());
+        if state::is_done(&network_phase) {
+            println_with_time!("Azure - Network for {} already exists, skipping", system_name);
+        }
+        else {
+            println_with_time!("Azure - Creating network for {}", system_name);
+            network_script.push_str(&emitter_system.network);
+            crate::shell_tools::run_command(&network_script, &SHELL.shell).panic_on_failure();
+            state::mark_done(network_phase);
+        }
 
         let pause_duration = Self::determine_pause_between_vms(emitter_system.vms.len());
         let mut machine_jh = Vec::with_capacity(emitter_system.vms.len());
         emitter_system.vms.iter().enumerate().for_each(|(idx, v)| {
+            let vm_name = pasir_vms[idx].name.clone();
+            let vm_phase = state::Phase::Vm(vm_name.clone());
+            if state::is_done(&vm_phase) || crate::cloud_functions::azure::vm_exists(&vm_name) {
+                println_with_time!("Azure - Machine {} of {} already exists, skipping", vm_name, system_name);
+                state::mark_done(vm_phase);
+                return;
+            }
             let v = v.to_owned(); //necessary for thread spawning
-            println_with_time!("Azure - Starting creation for {} of machine {}", system_name, pasir_vms[idx].name);
+            println_with_time!("Azure - Starting creation for {} of machine {}", system_name, vm_name);
             let jh = thread::spawn(move || {
                 crate::shell_tools::run_command(&format!("{}", v), &SHELL.shell).panic_on_failure();
+                state::mark_done(vm_phase);
             });
             machine_jh.push(jh);
             thread::sleep(pause_duration);
@@ -40,11 +56,29 @@ impl TaasPipeline for AzureTaasPipeline {
         println_with_time!("Azure - Finished creating system {}", system_name);
     }
 
-    fn run_script(machine_name: &str, script_text: &str) {
-        crate::cloud_functions::azure::send_and_exec_script_small(machine_name, script_text);
+    fn run_script_with_output(machine_name: &str, script_text: &str) -> String {
+        crate::cloud_functions::azure::send_and_exec_script_output(machine_name, script_text)
+    }
+
+    fn restart_machine(machine_name: &str) {
+        crate::cloud_functions::azure::restart_vm(machine_name);
     }
 
     fn get_public_ip(machine_name: &str) -> String {
         crate::cloud_functions::azure::get_public_ip(machine_name)
     }
+
+    fn find_shared_orchestrator(shared: &crate::orchestrator::Shared) -> Option<(String, String)> {
+        crate::cloud_functions::azure::shared_vm_ips(shared)
+    }
+
+    fn create_shared_orchestrator(shared: &crate::orchestrator::Shared) {
+        let (user, password) = crate::orchestrator::credentials();
+        let size = crate::orchestrator::override_size().unwrap_or_else(|| crate::orchestrator::SHARED_DEFAULT_SIZE.to_string());
+        crate::cloud_functions::azure::create_shared_orchestrator(shared, &size, user, &password);
+    }
+
+    fn connect_to_shared_orchestrator(shared: &crate::orchestrator::Shared, system_name: &str) {
+        crate::cloud_functions::azure::peer_with_shared(shared, system_name);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,173 @@
+// pipelines/distribution.rs
+//
+// How the files of the machines get to them. By default they go through the orchestrator webserver,
+// a dedicated VM that the machines download from. For small scenarios, the direct mode skips that VM
+// and pushes the files to every machine instead: with scp for the machines with remote access, and
+// otherwise with the cloud provider's run-command, in chunks small enough for a single command, which is
+// slow for large files. The features that need the machines to reach the webserver (DOWNLOAD steps,
+// barriers) are not available.
+
+use serde::{Serialize, Deserialize};
+use crate::pipelines::TaasPipeline;
+use crate::utils::run_parser::{Step, StepType};
+use crate::utils::global_config::DEFAULT_VALUES;
+use crate::paths;
+use crate::pasir;
+use crate::shell_tools;
+use crate::shell_tools::RunInfo;
+use crate::utils::global_config::SHELL;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+// size of the pieces of a file sent with one run-command, a single shell argument cannot be larger than 128KB
+const CHUNK_BYTES: usize = 48 * 1024;
+// above this size, pushing a file directly takes minutes
+const LARGE_FILE_BYTES: u64 = 4 * 1024 * 1024;
+// printed once a file has been pushed completely
+const PUSH_OK_MARKER: &str = "TAAS_PUSH_OK";
+// texts in the scenario files that only make sense with the webserver, and what they are
+const WEBSERVER_MENTIONS: [(&str, &str); 5] = [
+    (paths::BARRIER_HELPER_FN, "barriers"),
+    ("ORCHESTRATOR_IP", "the orchestrator IP address"),
+    ("WEBSERVER_IP", "the webserver IP address"),
+    ("WEBSERVER_URL", "the webserver URL"),
+    ("machines/orchestrator/", "the orchestrator machine"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    /// Through the orchestrator webserver VM
+    Webserver,
+    /// Directly to every machine, without an orchestrator VM
+    Direct,
+}
+
+/// Why the scenario cannot do without the webserver, if it cannot
+fn webserver_requirement(scenario: &str, steps: &[Step]) -> Option<String> {
+    if let Some(step) = steps.iter().find(|s| matches!(s.step_type, StepType::Download { .. })) {
+        return Some(format!("step {} downloads files from the machines", step.name));
+    }
+    let scenario_dir = format!("{}/{}", paths::SCENARIO_PATH, scenario);
+    WEBSERVER_MENTIONS.iter()
+        .find(|(text, _)| steps.iter().any(|s| s.scripts.values().any(|script| script.contains(text))) || dir_mentions(&scenario_dir, text))
+        .map(|(text, what)| format!("the scenario uses {} ({})", what, text))
+}
+
+/// True if any file under the folder contains the text
+fn dir_mentions(dir: &str, text: &str) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.filter_map(|e| e.ok()).any(|e| {
+        let path = e.path();
+        match path.is_dir() {
+            true => dir_mentions(&path.to_string_lossy(), text),
+            false => fs::read(&path).map(|content| String::from_utf8_lossy(&content).contains(text)).unwrap_or(false),
+        }
+    })
+}
+
+/// Picks the distribution mode from the `distribution` setting of system.yml (direct, webserver or auto, by default
+/// the one of config/default_values.yml). In auto mode, scenarios with few machines that do not need the webserver go direct.
+pub fn choose(setting: &Option<String>, scenario: &str, machine_count: usize, steps: &[Step]) -> Distribution {
+    let setting = setting.clone().unwrap_or_else(|| DEFAULT_VALUES.distribution.clone());
+    match setting.to_lowercase().as_ref() {
+        "webserver" => Distribution::Webserver,
+        "direct" => {
+            if let Some(reason) = webserver_requirement(scenario, steps) {
+                panic!("Error: the scenario is set to 'distribution: direct', but {}, which needs the orchestrator webserver. Please use 'distribution: webserver'.", reason);
+            }
+            Distribution::Direct
+        },
+        "auto" => match (machine_count <= DEFAULT_VALUES.direct_max_machines, webserver_requirement(scenario, steps)) {
+            (true, None) => Distribution::Direct,
+            _ => Distribution::Webserver,
+        },
+        other => panic!("Error: unknown distribution '{}', please use one of: direct, webserver, auto", other),
+    }
+}
+
+/// How to reach a machine with scp
+#[derive(Debug, Clone)]
+pub struct ScpTarget {
+    pub host: String,
+    pub user: String,
+    pub ssh_key: String,
+}
+
+impl ScpTarget {
+    /// None for the machines without remote access, which only run-commands reach
+    pub fn of<P: TaasPipeline + ?Sized>(vm: &pasir::machines::Vm) -> Option<ScpTarget> {
+        match vm.has_remote_access {
+            true => Some(ScpTarget { host: P::get_public_ip(&vm.name), user: vm.auth.user.clone(), ssh_key: vm.auth.ssh_key() }),
+            false => None,
+        }
+    }
+}
+
+/// Copies a local file to the given path on the machine, with scp when it can be reached that way and with run-commands otherwise.
+/// The file is only moved into place once complete.
+pub fn push_file<P: TaasPipeline + ?Sized>(machine: &str, scp: Option<&ScpTarget>, local_fn: &str, remote_fn: &str) {
+    match scp {
+        Some(target) => push_file_scp(machine, target, local_fn, remote_fn),
+        None => push_file_run_command::<P>(machine, local_fn, remote_fn),
+    }
+}
+
+/// The file lands in /tmp first, as the user may not be allowed to write at its final path
+fn push_file_scp(machine: &str, target: &ScpTarget, local_fn: &str, remote_fn: &str) {
+    let ssh_args = crate::utils::ssh_key::ssh_args(&target.ssh_key);
+    let file_name = Path::new(remote_fn).file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+    let partial = format!("/tmp/{}.taas_partial", file_name);
+    shell_tools::run_command_try_again(&format!("scp {} {} {}@{}:{}", ssh_args, local_fn, target.user, target.host, partial), &SHELL.shell, 8, Some(std::time::Duration::from_secs(1)))
+        .panic_on_failure();
+    let moved = shell_tools::run_command(&format!("ssh {} {}@{} 'sudo mkdir -p \"$(dirname {remote})\" && sudo mv {partial} {remote}'", ssh_args, target.user, target.host, remote = remote_fn, partial = partial), &SHELL.shell);
+    if moved.failure() {
+        panic!("Error: could not send {} to machine '{}'. Output:\n{}{}", local_fn, machine, moved.stdout(), moved.stderr());
+    }
+}
+
+fn push_file_run_command<P: TaasPipeline + ?Sized>(machine: &str, local_fn: &str, remote_fn: &str) {
+    let mut file = fs::File::open(local_fn).unwrap_or_else(|_| panic!("Error: could not open {} to send it to machine '{}'. Please check permissions", local_fn, machine));
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if size > LARGE_FILE_BYTES {
+        println_with_time!("Warning: sending {} ({} MB) directly to {}, this will take a while. The webserver distribution is faster for large files.", local_fn, size / (1024 * 1024), machine);
+    }
+
+    let partial = format!("{}.taas_partial", remote_fn);
+    let mut first = true;
+    let mut buffer = vec![0; CHUNK_BYTES];
+    loop {
+        let read = read_chunk(&mut file, &mut buffer).unwrap_or_else(|_| panic!("Error: could not read {}", local_fn));
+        if read == 0 && !first {
+            break;
+        }
+        let redirect = if first { ">" } else { ">>" };
+        let script = format!("mkdir -p \"$(dirname {remote})\"\necho {data} | base64 -d {redirect} {partial} && echo {ok}\n",
+                             remote = remote_fn, data = base64::encode(&buffer[..read]), redirect = redirect, partial = partial, ok = PUSH_OK_MARKER);
+        if !P::run_script_with_output(machine, &script).contains(PUSH_OK_MARKER) {
+            panic!("Error: could not send {} to machine '{}'", local_fn, machine);
+        }
+        first = false;
+        if read < CHUNK_BYTES {
+            break;
+        }
+    }
+    if !P::run_script_with_output(machine, &format!("mv {} {} && echo {}\n", partial, remote_fn, PUSH_OK_MARKER)).contains(PUSH_OK_MARKER) {
+        panic!("Error: could not send {} to machine '{}'", local_fn, machine);
+    }
+}
+
+/// Fills the buffer as much as possible, returns how many bytes were read
+fn read_chunk(file: &mut fs::File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut total = 0;
+    while total < buffer.len() {
+        match file.read(&mut buffer[total..])? {
+            0 => break,
+            n => total += n,
+        }
+    }
+    Ok(total)
+}
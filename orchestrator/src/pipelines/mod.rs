@@ -1,4 +1,9 @@
 pub mod azure_cli;
+mod scheduler;
+mod readiness;
+mod steps;
+pub mod distribution;
+mod placement;
 
 use crate::yamlsir;
 use crate::lasir;
@@ -9,6 +14,8 @@ use crate::utils::run_parser;
 use lasir::machines::LogicalSystem as lasirSystem;
 use pasir::machines::Vm as pasirVm;
 use run_parser::StepType;
+use crate::state::Phase;
+use distribution::Distribution;
 
 use std::fs;
 use std::path::Path;
@@ -22,7 +29,6 @@ use std::time::Duration;
 use crate::utils;
 use std::iter::Iterator;
 use crate::shell_tools;
-use crate::utils::global_config::SSH;
 use crate::utils::global_config::SHELL;
 
 use shell_tools::RunInfo;
@@ -36,26 +42,19 @@ const ONE_SEC: Duration = time::Duration::from_millis(1000);
 // const EIGHT_SECS: Duration = time::Duration::from_millis(5000);
 const RUN_STEP_PREFIX: &str = "step_run";
 const SETUP_STEP_PREFIX: &str = "step_setup";
+// printed by the download script once the files of a machine are in place
+const FILES_OK_MARKER: &str = "TAAS_FILES_OK";
 
 pub trait TaasPipeline {
 
     /// Given a set of PASIR VMs, returns the associate hostname / public IP address of each that has one
     /// Will also add then to the global map
     fn map_public_hostname_with_global(pasir_vms: &Vec<pasir::machines::Vm>) -> HashMap<String, String> {
-
-        fn global_repl_host(machine_name: &str, public_hostname: &str) {
-        
-            crate::post_deployment::add_global_replacement(&format!("machines/{}/public_ip", &machine_name), &format!("{}", &public_hostname));
-            crate::post_deployment::add_global_replacement(&format!("machines/{}/public_ip_address", &machine_name), &format!("{}", &public_hostname));
-            crate::post_deployment::add_global_replacement(&format!("machines/{}/public_host", &machine_name), &format!("{}", &public_hostname));
-            crate::post_deployment::add_global_replacement(&format!("machines/{}/public_hostname", &machine_name), &format!("{}", &public_hostname));
-        }
-
         let mut ret: HashMap<String, String> = HashMap::new();
         pasir_vms.iter().filter(|vm| vm.has_remote_access).for_each(|vm| {
             let phn = Self::get_public_ip(&vm.name);
             ret.insert(vm.name.clone(), phn.clone());
-            global_repl_host(&vm.name, &phn);
+            crate::post_deployment::add_machine_public_host(&vm.name, &phn);
         });
         ret
     }
@@ -69,13 +68,12 @@ pub trait TaasPipeline {
 
             let mut success = false;
             let mut counter = 4;
-
             while counter > 0 {
                 //TODO: get rid of clone
                 Self::setup_webserv(ip, machine_names.clone());
 
-                let fail1 = crate::shell_tools::run_command_no_output(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null orch@{} \"echo asdfgDDFjklqwe1234 | sudo -S systemctl status orche\"", SSH.custom_args, ip), &SHELL.shell).failure();
-                let fail2 = crate::shell_tools::run_command_no_output(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null orch@{} \"echo asdfgDDFjklqwe1234 | sudo -S ls /home/orch/machine_reports\"", SSH.custom_args, ip), &SHELL.shell).failure();
+                let fail1 = crate::shell_tools::run_command_no_output(&format!("ssh {} orch@{} \"sudo systemctl status orche\"", crate::orchestrator::ssh_args(), ip), &SHELL.shell).failure();
+                let fail2 = crate::shell_tools::run_command_no_output(&format!("ssh {} orch@{} \"sudo ls /home/orch/{}\"", crate::orchestrator::ssh_args(), ip, crate::orchestrator::remote_path("machine_reports")), &SHELL.shell).failure();
 
                 if !fail1 && !fail2 {
                     success = true;
@@ -86,39 +84,94 @@ pub trait TaasPipeline {
             }
 
         if !success {
-            panic!();
+            panic!("Error: the webserver could not be configured. Once the issue is fixed, the deployment can be continued with 'orchestrator resume'.");
         }
     }
 
     fn setup_webserv<'a, V>(ip: &str, machine_names: V)
         where V: IntoIterator<Item = String> {
 
-        std::fs::create_dir_all("test-deployment").unwrap_or_else(|_| panic!("Error: could not create temporary folder for deployment templates."));
-
-        std::fs::copy("webserver/webserver", "test-deployment/ws").unwrap_or_else(|_| panic!("Error: could not copy the webserver binary to the temporary folder. Make sure it exists at ./webserver/webserver, and the user has access to it."));
+        let common_data = crate::state::run_path(paths::COMMON_DATA_DIR);
+        let remote_common_data = crate::orchestrator::remote_path("common_data");
 
         // Common data
-        let common_data_dir = fs::read_dir("temp_common_data");
+        let common_data_dir = fs::read_dir(&common_data);
         if common_data_dir.is_ok() && common_data_dir.unwrap().count() > 0 {
-            crate::shell_tools::run_command(&format!("cd temp_common_data; tar czf common_data.tgz ./*"), &SHELL.shell).panic_on_failure();
-            crate::shell_tools::run_command_try_again(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null orch@{} \"mkdir -p common_data\"", SSH.custom_args, ip), &SHELL.shell, 8, None).panic_on_failure();
-            crate::shell_tools::run_command_try_again(&format!("scp {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null temp_common_data/common_data.tgz orch@{}:~/common_data/", SSH.custom_args, ip), &SHELL.shell, 8, Some(ONE_SEC));
-            crate::shell_tools::run_command_try_again(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null orch@{} \"cd common_data; tar xzf common_data.tgz\"", SSH.custom_args, ip), &SHELL.shell, 8, None);
+            utils::checksum::write_manifest(&common_data);
+            match crate::state::shared_orchestrator() {
+                // kept across runs on a shared orchestrator, so that only the common data that changed gets uploaded
+                Some(_) => {
+                    Self::upload_dir_to_orchestrator(ip, &common_data, crate::orchestrator::SHARED_COMMON_DATA_CACHE);
+                    let cached: Vec<String> = fs::read_dir(&common_data).unwrap_or_else(|_| panic!("Error: could not list {}. Please check permissions", common_data))
+                        .filter_map(|e| e.ok())
+                        .map(|e| format!("{}/{}", crate::orchestrator::SHARED_COMMON_DATA_CACHE, e.file_name().to_string_lossy()))
+                        .collect();
+                    crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"mkdir -p {dir} && cp -alf {} {dir}/\"", crate::orchestrator::ssh_args(), ip, cached.join(" "), dir = remote_common_data), &SHELL.shell, 8, Some(ONE_SEC)).panic_on_failure();
+                },
+                None => {
+                    crate::shell_tools::run_command(&format!("cd {}; tar czf common_data.tgz ./*", common_data), &SHELL.shell).panic_on_failure();
+                    crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"mkdir -p {}\"", crate::orchestrator::ssh_args(), ip, remote_common_data), &SHELL.shell, 8, None).panic_on_failure();
+                    crate::shell_tools::run_command_try_again(&format!("scp {} {}/common_data.tgz orch@{}:~/{}/", crate::orchestrator::ssh_args(), common_data, ip, remote_common_data), &SHELL.shell, 8, Some(ONE_SEC));
+                    crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"cd {}; tar xzf common_data.tgz\"", crate::orchestrator::ssh_args(), ip, remote_common_data), &SHELL.shell, 8, None);
+                },
+            }
         }
-        
-        crate::shell_tools::run_command_try_again(&format!("scp {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null test-deployment/ws orch@{}:~/ws", SSH.custom_args, ip), &SHELL.shell, 8, Some(ONE_SEC));
-        crate::shell_tools::run_command_no_output("rm test-deployment/ws", &SHELL.shell);
-        crate::shell_tools::run_command_try_again(&format!("scp {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null webserver/webserver_setup.sh orch@{}:~/webserver_setup.sh", SSH.custom_args, ip), &SHELL.shell, 8, Some(ONE_SEC));
-        crate::shell_tools::run_command_try_again(&format!("scp {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null webserver/orche.service orch@{}:~/orche.service", SSH.custom_args, ip), &SHELL.shell, 8, Some(ONE_SEC));
-
-        crate::shell_tools::run_command_try_again(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null orch@{} \"mkdir -p machine_reports\"", SSH.custom_args, ip), &SHELL.shell, 8, Some(ONE_SEC));
 
         let dirs: Vec<String> = machine_names.into_iter().collect();
-        let dirs = dirs.join(" machine_reports/");
-        let dirs = "machine_reports/".to_owned() + &dirs;
 
-        crate::shell_tools::run_command_try_again(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null orch@{} \"mkdir -p {}\"", SSH.custom_args, ip, dirs), &SHELL.shell, 8, Some(ONE_SEC));
-        crate::shell_tools::run_command_try_again(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null orch@{} \"echo asdfgDDFjklqwe1234 | sudo -S sh ~/webserver_setup.sh\"", SSH.custom_args, ip), &SHELL.shell, 8, Some(ONE_SEC));
+        // every machine gets its own token, the webserver refuses requests without one
+        let tokens_fn = crate::state::run_path(crate::orchestrator::WEBSERVER_TOKENS_FN);
+        let remote_tokens_fn = crate::orchestrator::remote_path(crate::orchestrator::WEBSERVER_TOKENS_FN);
+        let tokens: String = crate::state::webserver_tokens(&dirs).iter().map(|(machine, token)| format!("{} {}\n", machine, token)).collect();
+        utils::files::write_private(&tokens_fn, &tokens).unwrap_or_else(|_| panic!("Error: could not write {}. Please check permissions", tokens_fn));
+        crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"mkdir -p {}\"", crate::orchestrator::ssh_args(), ip, crate::orchestrator::remote_path(".")), &SHELL.shell, 8, Some(ONE_SEC));
+        crate::shell_tools::run_command_try_again(&format!("scp {} {} orch@{}:~/{}", crate::orchestrator::ssh_args(), tokens_fn, ip, remote_tokens_fn), &SHELL.shell, 8, Some(ONE_SEC));
+        crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"chmod 600 {}\"", crate::orchestrator::ssh_args(), ip, remote_tokens_fn), &SHELL.shell, 8, Some(ONE_SEC));
+
+        let machine_reports = crate::orchestrator::remote_path("machine_reports");
+        let dirs: Vec<String> = dirs.iter().map(|d| format!("{}/{}", machine_reports, d)).collect();
+        crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"mkdir -p {} {}\"", crate::orchestrator::ssh_args(), ip, machine_reports, dirs.join(" ")), &SHELL.shell, 8, Some(ONE_SEC));
+
+        // a shared orchestrator already runs the webserver, see create_orchestrator()
+        if crate::state::shared_orchestrator().is_none() {
+            Self::install_webserver(ip);
+        }
+    }
+
+    /// Installs the webserver (this very program, started with 'serve') and the barrier daemon as services of the orchestrator VM
+    fn install_webserver(ip: &str) {
+        let webserver_binary = match std::path::Path::new(paths::WEBSERVER_BINARY_OVERRIDE).is_file() {
+            true => std::path::PathBuf::from(paths::WEBSERVER_BINARY_OVERRIDE),
+            // a glibc build only runs if the VM's glibc is not older than this host's, which is checked below
+            false if cfg!(all(target_os = "linux", target_arch = "x86_64")) => std::env::current_exe().unwrap_or_else(|_| panic!("Error: could not find the path of the orchestrator executable")),
+            false => panic!("Error: the orchestrator VM needs a Linux x86_64 build of the orchestrator to serve files. Please build one (preferably static, with --target x86_64-unknown-linux-musl) and copy it to {}", paths::WEBSERVER_BINARY_OVERRIDE),
+        };
+
+        // moved into place, as the binary may be running already
+        crate::shell_tools::run_command_try_again(&format!("scp {} {} orch@{}:~/ws.new", crate::orchestrator::ssh_args(), webserver_binary.display(), ip), &SHELL.shell, 8, Some(ONE_SEC));
+        // a build that cannot run there would only leave the service restarting and the downloads timing out
+        let smoke_test = crate::shell_tools::run_command(&format!("ssh {} orch@{} \"chmod +x ws.new && ./ws.new serve --help\"", crate::orchestrator::ssh_args(), ip), &SHELL.shell);
+        if smoke_test.failure() {
+            panic!("Error: {} does not run on the orchestrator VM. Please build a static one with --target x86_64-unknown-linux-musl and copy it to {}. Output:\n{}{}",
+                webserver_binary.display(), paths::WEBSERVER_BINARY_OVERRIDE, smoke_test.stdout(), smoke_test.stderr());
+        }
+        crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"mv ws.new ws && touch {}\"", crate::orchestrator::ssh_args(), ip, crate::orchestrator::WEBSERVER_TOKENS_FN), &SHELL.shell, 8, Some(ONE_SEC));
+        crate::shell_tools::run_command_try_again(&format!("scp {} webserver/webserver_setup.sh orch@{}:~/webserver_setup.sh", crate::orchestrator::ssh_args(), ip), &SHELL.shell, 8, Some(ONE_SEC));
+        crate::shell_tools::run_command_try_again(&format!("scp {} webserver/orche.service orch@{}:~/orche.service", crate::orchestrator::ssh_args(), ip), &SHELL.shell, 8, Some(ONE_SEC));
+        crate::shell_tools::run_command_try_again(&format!("scp {} webserver/barrierd.sh webserver/orche-barrier.service orch@{}:~/", crate::orchestrator::ssh_args(), ip), &SHELL.shell, 8, Some(ONE_SEC));
+        crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"sudo sh ~/webserver_setup.sh\"", crate::orchestrator::ssh_args(), ip), &SHELL.shell, 8, Some(ONE_SEC));
+    }
+
+    /// Copies the content of a local folder into a folder under the home of the orchestrator user.
+    /// A shared orchestrator gets it with rsync (when installed here), so that only the files that changed are sent again.
+    fn upload_dir_to_orchestrator(ip: &str, local_dir: &str, remote_dir: &str) {
+        let rsync = crate::state::shared_orchestrator().is_some() && shell_tools::check_command_exist("rsync");
+        crate::shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"mkdir -p {}\"", crate::orchestrator::ssh_args(), ip, remote_dir), &SHELL.shell, 8, Some(ONE_SEC)).panic_on_failure();
+        let copy = match rsync {
+            true => format!("rsync -rz --checksum -e 'ssh {}' {}/ orch@{}:~/{}/", crate::orchestrator::ssh_args(), local_dir, ip, remote_dir),
+            false => format!("scp {} -r {}/* orch@{}:~/{}/", crate::orchestrator::ssh_args(), local_dir, ip, remote_dir),
+        };
+        crate::shell_tools::run_command_try_again(&copy, &SHELL.shell, 8, Some(ONE_SEC)).panic_on_failure();
     }
 
     // ====================================================================================================================
@@ -175,9 +228,45 @@ pub trait TaasPipeline {
     // Get back new data: Public IPs
     // Run Post-Deployment
 
-    fn run_v2(scenario: &str) {
+    fn run_v2(scenario: &str, options: crate::state::RunOptions) {
+        // fail before deploying anything
+        if options.strict && crate::lint::check::<Self>(scenario) > 0 {
+            panic!("Error: some replacement tokens of scenario {} have no value (see above), which --strict does not allow", scenario);
+        }
+        crate::state::start(scenario, options);
+        println_with_time!("Starting run {}", crate::state::run_id());
+        Self::run_with_teardown(scenario);
+    }
+
+    /// Continues the last deployment from its first incomplete phase (see the state module)
+    fn resume(run_id: Option<&str>) {
+        let scenario = crate::state::load(run_id);
+        println_with_time!("Resuming run {} of scenario {}...", crate::state::run_id(), scenario);
+        Self::run_with_teardown(&scenario);
+    }
+
+    /// Runs the deployment, then applies the teardown policy whether it succeeded or not,
+    /// before exiting with the exit code of the post-deployment script (or re-raising the failure)
+    fn run_with_teardown(scenario: &str) {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| Self::run_from_state(scenario)));
+        let success = match result {
+            Ok(exit_code) => exit_code == 0,
+            Err(_) => false,
+        };
+        crate::teardown::apply(success);
+
+        match result {
+            Ok(exit_code) => std::process::exit(exit_code),
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+
+    /// Runs all the phases of a deployment, skipping the ones already recorded as completed in the deployment state.
+    /// Returns the exit code of the post-deployment script, or 0 if there is none.
+    fn run_from_state(scenario: &str) -> i32 {
         println_with_time!("Generating system internal representation...");
         let yamlsir_root = Self::parse_yaml(scenario);
+        crate::state::set_teardown_if_unset(crate::teardown::TeardownPolicy::from_yamlsir(&yamlsir_root.teardown));
         let lasir_system = Self::yamlsir_to_lasir(&yamlsir_root);
         let (pasir_vms, pasir_subnet) = Self::lasir_to_pasir(&lasir_system);
 
@@ -201,16 +290,12 @@ pub trait TaasPipeline {
             ret
         };
         Self::prepare_common_data(&common_data_map);
-        println_with_time!("Creating orchestrator webserver...");
-        let ws_handle = thread::spawn(move || {
-            Self::create_orchestrator();
-        });
 
         // Recover run steps (if they exist)
         let pipeline_fn = format!("scenarios/{}/{}", scenario, paths::RUN_STEPS_FN);
         let run_steps = match Path::new(&pipeline_fn).exists() {
             true => {
-                run_parser::parse_run_list(&pipeline_fn)
+                run_parser::parse_run_list(&pipeline_fn, &pasir_vms)
             },
             false => {
                 println_with_time!("Warning: pipeline file is missing");
@@ -221,6 +306,23 @@ pub trait TaasPipeline {
             println_with_time!("Warning: pipeline file does not contain any steps");
         }
 
+        let distribution = crate::state::set_distribution_if_unset(distribution::choose(&yamlsir_root.distribution, scenario, pasir_vms.len(), &run_steps));
+        let ws_handle = match distribution {
+            Distribution::Webserver => {
+                if !crate::state::is_done(&Phase::Orchestrator) {
+                    crate::state::set_shared_orchestrator(crate::orchestrator::shared_config());
+                }
+                println_with_time!("Creating orchestrator webserver...");
+                Some(thread::spawn(move || {
+                    Self::create_orchestrator();
+                }))
+            },
+            Distribution::Direct => {
+                println_with_time!("Sending files directly to the machines, without orchestrator webserver");
+                None
+            },
+        };
+
         // CREATE SYSTEM
         // This is the specific functions which must take the pasir, and create itw own internal representation
         println_with_time!("Creating machines...");
@@ -231,16 +333,35 @@ pub trait TaasPipeline {
         });
 
         // Prepare and run webserver
-        ws_handle.join().expect("Error: The webserver could not be setup. Please check that ssh is properly configured on your machine by manually connecting to the orchestrator.");
-        let orch_ip = Self::get_public_ip("orchestrator");
-        println_with_time!("Preparing webserver files...");
-        Self::prepare_ws(&orch_ip, pasir_vms.iter().map(|v| v.name.to_owned()));
+        let orch_ip = ws_handle.map(|ws_handle| {
+            ws_handle.join().expect("Error: The webserver could not be setup. Please check that ssh is properly configured on your machine by manually connecting to the orchestrator.");
+            let orch_ip = Self::orchestrator_ip();
+            if !crate::state::is_done(&Phase::WebserverPrepared) {
+                println_with_time!("Preparing webserver files...");
+                Self::prepare_ws(&orch_ip, pasir_vms.iter().map(|v| v.name.to_owned()));
+                crate::state::mark_done(Phase::WebserverPrepared);
+            }
+            orch_ip
+        });
         system_jh.join().expect("Error: Could not properly create machines. Please look at the cloud-sepcific errors on the program and the website.");
+        if let Some(shared) = crate::state::shared_orchestrator() {
+            if !crate::state::is_done(&Phase::Peered) {
+                println_with_time!("Connecting the network of the run to shared orchestrator {}...", shared.name);
+                Self::connect_to_shared_orchestrator(&shared, "taas_run");
+                crate::state::mark_done(Phase::Peered);
+            }
+        }
+        let ws_host = orch_ip.as_ref().map(|_| Self::webserver_host());
         let public_hostname_map = Self::map_public_hostname_with_global(&pasir_vms);
-        Self::prepare_from_templates(scenario, &lasir_system, &pasir_vms, &pasir_subnet, &public_hostname_map, &run_steps);
 
-        // Get data for each machine
-        Self::push_data_to_machines(&pasir_vms, &common_data_map, &orch_ip);
+        // templates are not generated again once pushed, as the machines already hold their replaced version
+        if !crate::state::is_done(&Phase::DataPushed) {
+            Self::prepare_from_templates(scenario, &lasir_system, &pasir_vms, &pasir_subnet, &public_hostname_map, &run_steps);
+
+            // Get data for each machine
+            Self::push_data_to_machines(&pasir_vms, &common_data_map, ws_host.as_deref());
+            crate::state::mark_done(Phase::DataPushed);
+        }
 
         let mut last_deployment_info = String::with_capacity(pasir_vms.len() * 48); //about 48 characters per machine in the yml file
         last_deployment_info.push_str("[\n");
@@ -249,48 +370,52 @@ pub trait TaasPipeline {
             if vm.has_remote_access {
 
                 let pip = cloud_functions::azure::get_public_ip(&vm.name);
-                println_with_time!("Public IP address of {}: {}\n  Password: {}", &vm.name, &pip, &vm.auth.password);
-                let yml = format!("  {{\n      name: {},\n      username: {},\n      password: {},\n      hostname: {},\n  }},\n", vm.name, vm.auth.user, vm.auth.password, pip);
+                let ssh_key = vm.auth.ssh_key();
+                println_with_time!("Public IP address of {}: {}", &vm.name, &pip);
+                let yml = format!("  {{\n      name: {},\n      username: {},\n      password: {},\n      hostname: {},\n      ssh_key: {},\n  }},\n", vm.name, vm.auth.user, vm.auth.password, pip, ssh_key);
                 last_deployment_info.push_str(&yml);
             }
         }
 
         last_deployment_info.push_str("]");
-        fs::write("last_deployment_summary.yml", last_deployment_info).expect("Error: could not write new file last_deployment_summary.yml. Please check permissions");
+        let summary_fn = crate::state::run_path(paths::DEPLOYMENT_SUMMARY_FN);
+        utils::files::write_private(&summary_fn, &last_deployment_info).unwrap_or_else(|_| panic!("Error: could not write new file {}. Please check permissions", summary_fn));
+        println_with_time!("The credentials of the machines are in {}", summary_fn);
 
         let machine_name_user_map: HashMap<String, String> = pasir_vms.iter().map(|v| (v.name.clone(), v.auth.user.clone())).collect();
-        for (step_index, (step_type, all_scripts)) in run_steps.iter().enumerate() {
-            println_with_time!("Pipeline - Running step {} ({})", step_index, step_type);
-
-            let mut saved_jh = Vec::new();
-            for (machine, _) in all_scripts {
-                let file_suffix = match step_type {
-                    StepType::Run => RUN_STEP_PREFIX,
-                    StepType::Setup => SETUP_STEP_PREFIX,
-                };
-
-                //saving unique values for thread ownership
-                let user = machine_name_user_map[machine].clone(); 
-                let machine = machine.to_string();
-                // script filename must the the same as what is in the temporary files
-                let filename = format!("{}.{}.sh", file_suffix, step_index);
-                let jh = thread::spawn(move || {
-                    Self::run_script(&machine, &format!("cd /home/{}; sudo sh {}", user, filename));
-                });
-                saved_jh.push(jh);
-
-                // add small pause when doing setups to limit load on cloud provider (not on setup to reduce delay between each machine's start)
-                match step_type {
-                    StepType::Run => (),
-                    StepType::Setup => thread::sleep(ONE_SEC),
-                }
-            }
-
-            for jh in saved_jh {
-                jh.join().expect("Error: One of the pipeline scripts failed to execute, please look at the program output to see what went wrong.");
-            }
-            println_with_time!("Pipeline - Finished running step {} ({})", step_index, step_type);
-        }
+        let scp_targets: HashMap<String, distribution::ScpTarget> = match distribution {
+            Distribution::Direct => pasir_vms.iter().filter_map(|v| distribution::ScpTarget::of::<Self>(v).map(|target| (v.name.clone(), target))).collect(),
+            Distribution::Webserver => HashMap::new(),
+        };
+        let step_context = steps::StepContext {
+            scenario,
+            orch_ip: orch_ip.as_deref(),
+            ws_host: ws_host.as_deref(),
+            users: &machine_name_user_map,
+            scp_targets: &scp_targets,
+        };
+        let default_repeat = crate::state::options().repeat.unwrap_or(1);
+        crate::report::start(scenario);
+        scheduler::run_steps(&run_steps, |step_index, machine| {
+            let step = &run_steps[step_index];
+            let (file_suffix, repeat) = match &step.step_type {
+                StepType::Run => (RUN_STEP_PREFIX, step.repeat.unwrap_or(default_repeat)),
+                StepType::Setup => (SETUP_STEP_PREFIX, 1),
+                StepType::Upload { local, remote } => return steps::upload::<Self>(&step_context, step_index, machine, local, remote),
+                StepType::Download { remote, local } => return steps::download::<Self>(&step_context, machine, remote, local),
+                StepType::Reboot => return steps::reboot::<Self>(machine),
+                StepType::Wait { seconds } => return steps::wait(*seconds),
+                StepType::Local => return steps::local(&step.scripts[machine]),
+            };
+            // script filename must the the same as what is in the temporary files
+            let filename = format!("{}.{}.sh", file_suffix, step_index);
+            steps::script::<Self>(&step_context, step, machine, &filename, repeat);
+        },
+        |check, machine| {
+            readiness::run_check(check, machine, &pasir_vms, &pasir_subnet, Self::run_script_with_output)
+        });
+        crate::report::print_summary();
+        let regressed = Self::compare_with_baseline();
 
         // Handle post deployement (if user supplied a post-deployment script file)
         let post_deployment_fn = format!("{}/{}/{}", paths::SCENARIO_PATH, scenario, paths::POST_DEPLOYMENT_SCRIPT_FN);
@@ -303,13 +428,29 @@ pub trait TaasPipeline {
                 let postd_script: String = String::from_utf8(filename).unwrap_or_else(|_| panic!("Error: could not coerce post-deployment script into valid utf-8"));
                 let exit_code = crate::post_deployment::run(&postd_script).exit_code();
                 println_with_time!("Finished executing post_deployment script. Now exiting with the script's exit code...");
-                std::process::exit(exit_code as i32);
+                match (exit_code, regressed) {
+                    (0, true) => 1,
+                    (code, _) => code as i32,
+                }
             },
-            Err(_) => (),
+            Err(_) => if regressed { 1 } else { 0 },
         }
     }
 
-    fn push_data_to_machines(vms: &Vec<pasirVm>, common_data_map: &HashMap<String, Vec<String>>, orch_pip: &str) {
+    /// Compares the report of the run with the baseline given with `--baseline`, if any. Returns true if anything regressed.
+    fn compare_with_baseline() -> bool {
+        let options = crate::state::options();
+        match &options.baseline {
+            Some(baseline) => {
+                let threshold = options.threshold_percent.unwrap_or(crate::utils::global_config::DEFAULT_VALUES.regression_threshold_percent);
+                crate::report::compare::compare(&crate::report::load(baseline), &crate::report::load(&crate::state::run_id()), threshold) > 0
+            },
+            None => false,
+        }
+    }
+
+    /// Gets the files of every machine to it, from the webserver at `orch_pip` (as reached by the machines), or directly if there is none
+    fn push_data_to_machines(vms: &[pasirVm], common_data_map: &HashMap<String, Vec<String>>, orch_pip: Option<&str>) {
         println_with_time!("Running setup scripts on VMs...");
 
         let mut jhs = Vec::new();
@@ -318,9 +459,14 @@ pub trait TaasPipeline {
             let common_datas = common_data_map.get(&v.name).map(|m| m.clone());
             //clones in order for threads to have their own copies
             let v = v.clone();
-            let orch_pip = orch_pip.to_owned();
+            let orch_pip = orch_pip.map(|ip| ip.to_owned());
             let jh = thread::spawn(move || {
-                Self::get_all_vm_files_v2(&v.name, &v.auth.user, &orch_pip, &common_datas);
+                // without webserver, the files are pushed from here
+                let scp = match orch_pip {
+                    Some(_) => None,
+                    None => distribution::ScpTarget::of::<Self>(&v),
+                };
+                Self::get_all_vm_files_v2(&v.name, &v.auth.user, orch_pip.as_deref(), scp.as_ref(), &common_datas);
             });
             jhs.push(jh);
             thread::sleep(pause_duration);
@@ -334,40 +480,77 @@ pub trait TaasPipeline {
     }
 
     #[inline]
-    fn get_all_vm_files_v2(vm_name: &str, vm_username: &str, ws_ip: &str, common_data: &Option<Vec<String>>) {
+    /// Downloads the files of the machine and its common data from the webserver (or sends them directly without webserver),
+    /// and checks them against the checksums computed here before extracting anything
+    fn get_all_vm_files_v2(vm_name: &str, vm_username: &str, ws_ip: Option<&str>, scp: Option<&distribution::ScpTarget>, common_data: &Option<Vec<String>>) {
 
-        let all_files = std::fs::read_dir(format!("test-deployment/{}", vm_name)).unwrap();
+        let vm_dir = format!("{}/{}", crate::state::run_path(paths::TEST_DEPLOYMENT_DIR), vm_name);
+        let manifest_fn = format!("{}/{}", vm_dir, utils::checksum::MANIFEST_FN);
+        let mut manifest = fs::read_to_string(&manifest_fn).unwrap_or_else(|_| panic!("Error: could not open {}. Please check permissions", manifest_fn));
         let mut get_script = String::new();
 
         get_script.push_str(&format!("cd /home/{}\n", vm_username));
+        get_script.push_str("set -e\n");
 
-        for file in all_files {
+        // (URL path on the webserver, local file, name on the machine)
+        let mut files: Vec<(String, String, String)> = Vec::new();
+        for file in std::fs::read_dir(&vm_dir).unwrap() {
             let filename = file.unwrap().file_name().to_str().unwrap().to_string();
-            match SHELL.download_tool.as_ref() {
-                "curl" => get_script.push_str(&format!("curl -o {} http://{}:8000/{}/{}\n", filename, ws_ip, vm_name, filename)),
-                "wget" => get_script.push_str(&format!("wget http://{}:8000/{}/{}\n", ws_ip, vm_name, filename)),
-                _ => panic!("Error: using unsupported download tool '', please use either curl or wget"),
+            if filename != utils::checksum::MANIFEST_FN {
+                files.push((format!("{}/{}", vm_name, filename), format!("{}/{}", vm_dir, filename), filename));
+            }
+        }
+        if let Some(cd) = common_data {
+            for common_file in cd {
+                let local_fn = format!("{}/{}", crate::state::run_path(paths::COMMON_DATA_DIR), common_file);
+                if std::path::Path::new(&local_fn).is_file() {
+                    manifest.push_str(&utils::checksum::manifest_line(&local_fn, common_file));
+                }
+                files.push((format!("common_data/{}", common_file), local_fn, common_file.clone()));
             }
         }
 
-        match common_data {
-            Some(cd) => {
-                for common_file in cd {
+        match ws_ip {
+            Some(ws_ip) => {
+                let token = crate::state::webserver_token(vm_name);
+                let auth = crate::orchestrator::auth_args(&token);
+                get_script.push_str(&format!("echo {} > {}\n", token, crate::orchestrator::MACHINE_TOKEN_FN));
+                get_script.push_str(&format!("chown {}:{} {}\n", vm_username, vm_username, crate::orchestrator::MACHINE_TOKEN_FN));
+                get_script.push_str(&format!("chmod 600 {}\n", crate::orchestrator::MACHINE_TOKEN_FN));
+                for (url_path, _, filename) in files {
                     match SHELL.download_tool.as_ref() {
-                        "curl" => get_script.push_str(&format!("curl -o {} http://{}:8000/common_data/{}\n", common_file, ws_ip, common_file)),
-                        "wget" => get_script.push_str(&format!("wget http://{}:8000/common_data/{}\n", ws_ip, common_file)),
+                        "curl" => get_script.push_str(&format!("curl -sf {} -o {} {}/{}\n", auth, filename, crate::orchestrator::base_url(ws_ip), url_path)),
+                        "wget" => get_script.push_str(&format!("wget -q {} -O {} {}/{}\n", auth, filename, crate::orchestrator::base_url(ws_ip), url_path)),
                         _ => panic!("Error: using unsupported download tool '', please use either curl or wget"),
                     }
                 }
             },
-            None => (),
+            None => {
+                for (_, local_fn, filename) in files {
+                    distribution::push_file::<Self>(vm_name, scp, &local_fn, &format!("/home/{}/{}", vm_username, filename));
+                }
+            },
         }
 
-        get_script.push_str(&format!("tar xzf {}.tgz\n", vm_name));
+        // the expected checksums come with this script, not from the webserver
+        get_script.push_str(&format!("cat > {} << 'TAAS_MANIFEST_END'\n{}TAAS_MANIFEST_END\n", utils::checksum::MANIFEST_FN, manifest));
+        get_script.push_str(&format!("sha256sum -c --quiet {}\n", utils::checksum::MANIFEST_FN));
+        get_script.push_str(&format!("rm -f {}\n", utils::checksum::MANIFEST_FN));
+
+        // replaces the entries of a previous attempt, if any
+        get_script.push_str(&format!("if [ -e {hosts} ]; then sudo sed -i '/^{begin}$/,/^{end}$/d' /etc/hosts; sudo sh -c 'cat {hosts} >> /etc/hosts'; rm -f {hosts}; fi\n",
+                                     hosts = paths::HOSTS_FRAGMENT_FN, begin = utils::roles::HOSTS_BEGIN_MARKER, end = utils::roles::HOSTS_END_MARKER));
+        // the folders that already exist (starting with the home folder) keep their owner and permissions
+        get_script.push_str(&format!("if [ -e {vm}.tgz ]; then tar xzf {vm}.tgz --no-same-owner --no-overwrite-dir; fi\n", vm = vm_name));
 
-        get_script.push_str(&format!("sudo chown {}:{} ./*\n", vm_username, vm_username));
+        get_script.push_str(&format!("sudo chown -R {}:{} .\n", vm_username, vm_username));
         get_script.push_str(&format!("rm -f {}.tgz\n", vm_name));
-        Self::run_script(&vm_name, &get_script);
+        get_script.push_str(&format!("if [ -e {script} ]; then sudo sh {script}; rm -f {script}; fi\n", script = placement::PLACEMENT_SCRIPT_FN));
+        get_script.push_str(&format!("echo {}\n", FILES_OK_MARKER));
+        let output = Self::run_script_with_output(vm_name, &get_script);
+        if !output.contains(FILES_OK_MARKER) {
+            panic!("Error: machine '{}' could not download or verify its files from the webserver. Output:\n{}", vm_name, output);
+        }
     }
 
     fn prepare_from_templates(
@@ -376,14 +559,35 @@ pub trait TaasPipeline {
         pasir_vms: &Vec<pasirVm>,
         pasir_network: &Vec<pasir::connections::Subnet>,
         hostname_map: &HashMap<String, String>,
-        run_steps_map: &Vec<(run_parser::StepType, HashMap<String, String>)>,
+        run_steps_map: &[run_parser::Step],
     ) {
         println_with_time!("Creating VM Role -> IP map...");
-        let (full_ip_repl_map, singleton_ip_repl_map) = utils::roles::create_vm_local_ip_mapping(lasir_system, &pasir_vms, &pasir_network);
-        let mut vm_specific_repl_map = utils::roles::create_ip_string_replacement_map(full_ip_repl_map, singleton_ip_repl_map);
+        let vm_specific_repl_map = Self::vm_replacement_maps(lasir_system, pasir_vms, pasir_network);
+
+        // installed on the machines along with their files, so before any SETUP step
+        for (vm, hosts) in pasir_vms.iter().zip(utils::roles::create_hosts_fragments(lasir_system, pasir_network)) {
+            let vm_dir = format!("{}/{}", crate::state::run_path(paths::TEST_DEPLOYMENT_DIR), vm.name);
+            std::fs::create_dir_all(&vm_dir).unwrap_or_else(|_| panic!("Error: could not create temporary folder for deployment templates."));
+            std::fs::write(format!("{}/{}", vm_dir, paths::HOSTS_FRAGMENT_FN), hosts).unwrap_or_else(|_| panic!("Error: could not write {} for machine {}. Please check permissions", paths::HOSTS_FRAGMENT_FN, vm.name));
+        }
 
         println_with_time!("Sending scripts and data to Webserver...");
-        //TODO; make this in special function
+        let deployment_templates_folder = format!("{}/{}/{}", paths::SCENARIO_PATH, scenario_name, paths::DEPLOYMENT_TEMPLATES_PATH); //TODO: put this string in global file
+        Self::replace_templates_for_deploy(&deployment_templates_folder, pasir_vms, hostname_map, &vm_specific_repl_map, run_steps_map);
+    }
+
+    /// Replacements specific to each machine, in the order of the PASIR VMs: the addresses and attributes of its roles, its name and user
+    fn vm_replacement_maps(
+        lasir_system: &lasirSystem<lasir::connections::VmConnectionLogicalV2>,
+        pasir_vms: &Vec<pasirVm>,
+        pasir_network: &Vec<pasir::connections::Subnet>,
+    ) -> Vec<HashMap<String, String>> {
+        let (full_ip_repl_map, singleton_ip_repl_map) = utils::roles::create_vm_local_ip_mapping(lasir_system, pasir_vms, pasir_network);
+        let mut vm_specific_repl_map = utils::roles::create_ip_string_replacement_map(full_ip_repl_map, singleton_ip_repl_map);
+        for (repl_map, attribute_map) in vm_specific_repl_map.iter_mut().zip(utils::roles::create_attribute_replacement_map(lasir_system)) {
+            repl_map.extend(attribute_map);
+        }
+
         for idx in 0..vm_specific_repl_map.len() {
             vm_specific_repl_map[idx].insert("NAME".to_string(), pasir_vms[idx].name.clone());
             vm_specific_repl_map[idx].insert("name".to_string(), pasir_vms[idx].name.clone());
@@ -400,33 +604,46 @@ pub trait TaasPipeline {
             vm_specific_repl_map[idx].insert("USERNAME".to_string(), pasir_vms[idx].auth.user.clone());
             vm_specific_repl_map[idx].insert("username".to_string(), pasir_vms[idx].auth.user.clone());
             vm_specific_repl_map[idx].insert("password".to_string(), pasir_vms[idx].auth.password.clone());
-            vm_specific_repl_map[idx].insert("PASSWORD".to_string(), pasir_vms[idx].auth.user.clone());
-            vm_specific_repl_map[idx].insert("pass".to_string(), pasir_vms[idx].auth.user.clone());
-            vm_specific_repl_map[idx].insert("PASS".to_string(), pasir_vms[idx].auth.user.clone());
+            vm_specific_repl_map[idx].insert("PASSWORD".to_string(), pasir_vms[idx].auth.password.clone());
+            vm_specific_repl_map[idx].insert("pass".to_string(), pasir_vms[idx].auth.password.clone());
+            vm_specific_repl_map[idx].insert("PASS".to_string(), pasir_vms[idx].auth.password.clone());
         }
+        vm_specific_repl_map
+    }
 
+    /// Replacements shared by all machines: the address of the webserver (if any) and the public hostname of the machines having one
+    fn run_replacement_map(ws_host: Option<&str>, pip_map: &HashMap<String, String>) -> HashMap<String, String> {
         let mut replacement_map: HashMap<String, String> = HashMap::new();
-        let deployment_templates_folder = format!("{}/{}/{}", paths::SCENARIO_PATH, scenario_name, paths::DEPLOYMENT_TEMPLATES_PATH); //TODO: put this string in global file
-        Self::replace_templates_for_deploy(&deployment_templates_folder, &pasir_vms, hostname_map, &mut replacement_map, &vm_specific_repl_map, run_steps_map);
+        if let Some(ws_host) = ws_host {
+            replacement_map.insert("ORCHESTRATOR_IP".to_string(), ws_host.to_string());
+            replacement_map.insert("WEBSERVER_IP".to_string(), ws_host.to_string());
+            replacement_map.insert("WEBSERVER_URL".to_string(), crate::orchestrator::base_url(ws_host));
+        }
+        pip_map.iter().for_each(|(k, v)| { replacement_map.insert(k.to_string(), v.to_string()); });
+        replacement_map
     }
 
     fn replace_templates_for_deploy(
                                 deployment_templates_folder: &str,
                                 pasir_vms: &Vec<crate::pasir::machines::Vm>,
                                 pip_map: &HashMap<String, String>,
-                                replacement_map: &mut HashMap<String, String>,
                                 vm_specific_repl_map: &Vec<HashMap<String, String>>,
-                                run_steps_map: &Vec<(run_parser::StepType, HashMap<String, String>)>,
+                                run_steps_map: &[run_parser::Step],
                             ) {
 
-        let public_ip_str = format!("{}", Self::get_public_ip("orchestrator"));
-        replacement_map.insert("ORCHESTRATOR_IP".to_string(), public_ip_str.clone());
-        replacement_map.insert("WEBSERVER_IP".to_string(), public_ip_str.clone());
- 
-        pip_map.iter().for_each(|(k, v)| { replacement_map.insert(k.to_string(), v.to_string()); });
+        let orch_ip = match crate::state::distribution() {
+            Distribution::Webserver => Some(Self::orchestrator_ip()),
+            Distribution::Direct => None,
+        };
+        let replacement_map = Self::run_replacement_map(orch_ip.as_ref().map(|_| Self::webserver_host()).as_deref(), pip_map);
         Self::prepare_template_configs_for_vms(&deployment_templates_folder, &pasir_vms, &replacement_map, vm_specific_repl_map, run_steps_map);
+        for vm in pasir_vms {
+            utils::checksum::write_manifest(&format!("{}/{}", crate::state::run_path(paths::TEST_DEPLOYMENT_DIR), vm.name));
+        }
 
-        shell_tools::run_command_try_again(&format!("scp {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null -r test-deployment/* orch@{}:~", SSH.custom_args, public_ip_str), &SHELL.shell, 8, Some(ONE_SEC));
+        if let Some(orch_ip) = orch_ip {
+            Self::upload_dir_to_orchestrator(&orch_ip, &crate::state::run_path(paths::TEST_DEPLOYMENT_DIR), &crate::orchestrator::remote_path("."));
+        }
     }
 
     #[inline]
@@ -436,10 +653,12 @@ pub trait TaasPipeline {
         vms: &Vec<pasir::machines::Vm>,
         replacement_map: &HashMap<String, String>,
         vm_specific_repl_map: &Vec<HashMap<String, String>>,
-        run_steps_map: &Vec<(run_parser::StepType, HashMap<String, String>)>,
+        run_steps_map: &[run_parser::Step],
     ) {
 
         let vm_count: usize = vms.len();
+        let test_deployment = crate::state::run_path(paths::TEST_DEPLOYMENT_DIR);
+        let template_deployment = crate::state::run_path(paths::TEMPLATE_DEPLOYMENT_DIR);
 
         let mut replacement_yml = String::with_capacity(vm_count * replacement_map.len()*64); //assume 64 characters per vm for yml file
         replacement_yml.push_str("[\n");
@@ -452,61 +671,110 @@ pub trait TaasPipeline {
             let temp_vm_specific_repl_map: HashMap<String, String> = vm_specific_repl_map[vm_idx].clone();
             temp_replacement_map.extend(temp_vm_specific_repl_map);
             all_machine_specific_replacement_maps.insert(vm.name.clone(), temp_replacement_map.clone()); // copying the temporary replacement map for later use in pipeline run
-            if vm.config_template != "" {
-                std::fs::create_dir_all(format!("temp-template-deployment/{}", vm.name)).unwrap_or_else(|_| panic!("Error: could not create temporary folder for deployment templates."));
-                std::fs::create_dir_all(format!("test-deployment/{}", vm.name)).unwrap_or_else(|_| panic!("Error: could not create temporary folder for deployment templates."));
 
-                //TODO: put this in a function in replace
-                let all_to_replace = std::fs::read_dir(format!("{}/{}/replace", deployment_templates_folder, vm.config_template));
-                
-                //TODO: use a match statement with the Result<_>
-                if all_to_replace.is_ok() {
-                    let list_replace = all_to_replace.unwrap();
-                    for fil in list_replace {
-                        let fil = fil.unwrap();
-                        let path = fil.path();
-                        let filename = path.file_name().unwrap().to_str().unwrap();
-                        replace::copy_and_replace_v2(&format!("{}", &path.display()),
-                                                &format!("temp-template-deployment/{}/{}", vm.name, &filename),
-                                                &temp_replacement_map,
-                                                replace::ReplaceFailPolicy::Warn);
-                    }
-                }
-
-                shell_tools::run_command_no_output(&format!("cp -rf {}/{}/data/* temp-template-deployment/{}/", deployment_templates_folder, vm.config_template, vm.name), &SHELL.shell);
-                shell_tools::run_command_no_output(&format!("cd temp-template-deployment/{}; tar czf {}.tgz ./*; cp {}.tgz ../../test-deployment/{}/", vm.name, vm.name, vm.name, vm.name), &SHELL.shell);
+            // every machine gets the barrier helper, so that its scripts can wait for each other (through the webserver)
+            std::fs::create_dir_all(format!("{}/{}", test_deployment, vm.name)).unwrap_or_else(|_| panic!("Error: could not create temporary folder for deployment templates."));
+            if crate::state::distribution() == Distribution::Webserver {
+                replace::copy_and_replace_v2(paths::BARRIER_HELPER_TEMPLATE,
+                                        &format!("{}/{}/{}", test_deployment, vm.name, paths::BARRIER_HELPER_FN),
+                                        &temp_replacement_map,
+                                        replace::ReplaceFailPolicy::Panic);
+            }
+            if vm.config_template != "" {
+                std::fs::create_dir_all(format!("{}/{}", template_deployment, vm.name)).unwrap_or_else(|_| panic!("Error: could not create temporary folder for deployment templates."));
+                std::fs::create_dir_all(format!("{}/{}", test_deployment, vm.name)).unwrap_or_else(|_| panic!("Error: could not create temporary folder for deployment templates."));
+
+                let template_dir = format!("{}/{}", deployment_templates_folder, vm.config_template);
+                let archive_dir = format!("{}/{}", template_deployment, vm.name);
+                replace::copy_and_replace_dir(&format!("{}/replace", template_dir), &archive_dir, &temp_replacement_map, replace::run_fail_policy());
+                shell_tools::run_command_no_output(&format!("cp -a {}/data/. {}/", template_dir, archive_dir), &SHELL.shell);
+                placement::write_script(&template_dir, &archive_dir);
+                // the whole tree, including hidden files, with the permissions of the files
+                shell_tools::run_command_no_output(&format!("tar czf {}/{}/{}.tgz -C {} .", test_deployment, vm.name, vm.name, archive_dir), &SHELL.shell);
                 replacement_yml.push_str(&Self::generate_replacement_yml(&vm.name, &temp_replacement_map))
             }
         }
 
         //handle run steps
         let valid_vm_names: Vec<_> = vms.iter().map(|v| v.name.clone()).collect();
-        for (run_index, (script_type, steps)) in run_steps_map.iter().enumerate() {
-            for (machine_name, script) in steps {
+        for (run_index, step) in run_steps_map.iter().enumerate() {
+            if step.step_type.runs_on_host() {
+                continue;
+            }
+            for (machine_name, script) in &step.scripts {
                 // check if machine name given in step run exists
                 valid_vm_names.iter().position(|x| x == machine_name).expect(&format!("Error: the run step file references machine '{}', but it is not declared in the system description.", machine_name));
-                let file_suffix = match script_type {
+                let file_suffix = match step.step_type {
                     StepType::Run => RUN_STEP_PREFIX,
                     StepType::Setup => SETUP_STEP_PREFIX,
+                    _ => continue, // the other steps do not have a script to send
                 };
                 
-                let filename = format!("test-deployment/{}/{}.{}.sh", machine_name, file_suffix, run_index);
+                let filename = format!("{}/{}/{}.{}.sh", test_deployment, machine_name, file_suffix, run_index);
                 
                 replace::replace_and_write(script,
                                             &filename,
                                             &all_machine_specific_replacement_maps[machine_name],
-                                            replace::ReplaceFailPolicy::Warn,
+                                            replace::run_fail_policy(),
                                             Some(paths::RUN_STEPS_FN.to_string()));
             }
         }
 
         replacement_yml.push_str("]");
-        std::fs::write("last_deployment_replacements.yml", replacement_yml).expect("Could not write last_deployment_replacements.yml. Please check file permissions");
+        utils::files::write_private(crate::state::run_path(paths::DEPLOYMENT_REPLACEMENTS_FN), &replacement_yml).unwrap_or_else(|_| panic!("Could not write {}. Please check file permissions", paths::DEPLOYMENT_REPLACEMENTS_FN));
     }
 
     fn create_orchestrator() {
+        if let Some(shared) = crate::state::shared_orchestrator() {
+            Self::check_shared_orchestrator(&shared);
+            crate::state::mark_done(Phase::Orchestrator);
+            return;
+        }
         let (machine, subnet) = crate::orchestrator::create();
+        if crate::state::is_done(&Phase::Orchestrator) {
+            return;
+        }
         Self::create_system(&vec![machine], &vec![subnet], "webserver");
+        crate::state::mark_done(Phase::Orchestrator);
+    }
+
+    /// Makes sure the shared orchestrator exists and serves files, creating it or setting its webserver up again if needed
+    fn check_shared_orchestrator(shared: &crate::orchestrator::Shared) {
+        let ip = match Self::find_shared_orchestrator(shared) {
+            Some((ip, _)) => {
+                println_with_time!("Reusing shared orchestrator {} of resource group {}", shared.name, shared.resource_group);
+                ip
+            },
+            None => {
+                println_with_time!("Shared orchestrator {} not found, creating it in resource group {}...", shared.name, shared.resource_group);
+                Self::create_shared_orchestrator(shared);
+                Self::find_shared_orchestrator(shared).map(|(ip, _)| ip).unwrap_or_else(|| panic!("Error: shared orchestrator {} could not be created", shared.name))
+            },
+        };
+        if !crate::orchestrator::is_healthy(&ip) {
+            println_with_time!("The webserver of shared orchestrator {} is not running, setting it up...", shared.name);
+            Self::install_webserver(&ip);
+            if !crate::orchestrator::is_healthy(&ip) {
+                panic!("Error: the webserver of shared orchestrator {} could not be started. Please check the 'orche' service on the VM ({}), or remove the shared section of {} to use an orchestrator per run.", shared.name, ip, paths::WS_CONFIG);
+            }
+        }
+    }
+
+    /// Public IP address of the orchestrator VM of the run (its own or the shared one), used from this host
+    fn orchestrator_ip() -> String {
+        match crate::state::shared_orchestrator() {
+            Some(shared) => Self::find_shared_orchestrator(&shared).map(|(public, _)| public).unwrap_or_else(|| panic!("Error: shared orchestrator {} does not exist anymore", shared.name)),
+            None => Self::get_public_ip("orchestrator"),
+        }
+    }
+
+    /// Address the machines reach the webserver at: the public IP of the run's own orchestrator,
+    /// or the private IP of the shared one, through the peering of the networks
+    fn webserver_host() -> String {
+        match crate::state::shared_orchestrator() {
+            Some(shared) => Self::find_shared_orchestrator(&shared).map(|(_, private)| private).unwrap_or_else(|| panic!("Error: shared orchestrator {} does not exist anymore", shared.name)),
+            None => Self::get_public_ip("orchestrator"),
+        }
     }
 
     #[inline]
@@ -515,19 +783,14 @@ pub trait TaasPipeline {
         //takes all common datas for all machines and make sure they are only referenced once
         let res: HashSet<String> = HashSet::from_iter(common_data_map.values().fold(Vec::new(), |mut acc, vec| { acc.extend_from_slice(vec.as_slice()); acc }));
 
-        shell_tools::run_command("mkdir -p temp_common_data", &SHELL.shell).panic_on_failure();
+        let common_data = crate::state::run_path(paths::COMMON_DATA_DIR);
+        shell_tools::run_command(&format!("mkdir -p {}", common_data), &SHELL.shell).panic_on_failure();
 
         for data in res {
-            shell_tools::run_command(&format!("cp -rf scenarios/common_data/{} temp_common_data/", data), &SHELL.shell).panic_on_failure();
+            shell_tools::run_command(&format!("cp -rf scenarios/common_data/{} {}/", data, common_data), &SHELL.shell).panic_on_failure();
         }
     }
 
-    fn run_script_from_file(machine_name: &str, script_fn: &str) {
-
-        let script_text = fs::read_to_string(script_fn).expect(&format!("Error: cannot open {} properly. Check file permission and text encoding.", script_fn));
-        Self::run_script(machine_name, &script_text);
-    }
-
     /// Small helper function intended to be used to make pauses between the creation of multiple machines in parralel
     /// This is done so to not overwhelm Azure with a huge number of VMs created at the same time.
     /// The idea is that the pause increases as the number of machines to be created increases.
@@ -541,9 +804,21 @@ pub trait TaasPipeline {
 
     // ================================== CLOUD SPECIFIC ======================================
 
-    fn run_script(machine_name: &str, script_text: &str);
+    /// Runs a script on the machine and returns its output
+    fn run_script_with_output(machine_name: &str, script_text: &str) -> String;
+
+    /// Restarts the machine, returning once it can run scripts again
+    fn restart_machine(machine_name: &str);
 
     fn create_system(pasir_vms: &Vec<pasir::machines::Vm>, pasir_network: &Vec<pasir::connections::Subnet>, system_name: &str);
 
     fn get_public_ip(machine_name: &str) -> String;
+
+    /// Public and private IP addresses of the shared orchestrator, None if it does not exist
+    fn find_shared_orchestrator(shared: &crate::orchestrator::Shared) -> Option<(String, String)>;
+
+    fn create_shared_orchestrator(shared: &crate::orchestrator::Shared);
+
+    /// Lets the machines of the given system reach the shared orchestrator through its private IP address
+    fn connect_to_shared_orchestrator(shared: &crate::orchestrator::Shared, system_name: &str);
 }
\ No newline at end of file
@@ -0,0 +1,49 @@
+// pipelines/placement.rs
+//
+// The files of a deployment template land in the home folder of the machine, with the structure they have in
+// replace/ and data/. A placement.yml next to them can also install some of them elsewhere on the machine:
+//   etc/nginx/conf.d/site.conf: /etc/nginx/conf.d/site.conf
+//   etc/nginx: /etc/nginx
+// The keys are paths inside replace/ or data/ (files or whole folders), the values absolute paths on the machine.
+// This is done with a script shipped in the archive of the machine, run as root once the archive is extracted.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use crate::paths;
+
+/// Name of the script in the archive of a machine
+pub const PLACEMENT_SCRIPT_FN: &str = ".taas_placement.sh";
+
+/// Writes the placement script of a deployment template into the folder the archive of the machine is made from,
+/// if the template has a placement.yml
+pub fn write_script(template_dir: &str, archive_dir: &str) {
+    let placement_fn = format!("{}/{}", template_dir, paths::PLACEMENT_FN);
+    let raw = match fs::read_to_string(&placement_fn) {
+        Ok(raw) => raw,
+        Err(_) => return,
+    };
+    let placement: BTreeMap<String, String> = serde_yaml::from_str(&raw).unwrap_or_else(|e| panic!("Error: {} is not a valid placement file, it should map paths of the template to absolute paths on the machine. Issue:\n{}\n", placement_fn, e));
+
+    let mut script = String::from("set -e\n");
+    for (source, target) in &placement {
+        if !target.starts_with('/') {
+            panic!("Error: in {}, '{}' should be placed at an absolute path, not '{}'", placement_fn, source, target);
+        }
+        let source = source.trim_start_matches("./").trim_end_matches('/');
+        let is_dir = match ["replace", "data"].iter().map(|dir| Path::new(template_dir).join(dir).join(source)).find(|path| path.exists()) {
+            Some(path) => path.is_dir(),
+            None => panic!("Error: in {}, '{}' is neither in replace/ nor in data/", placement_fn, source),
+        };
+        match is_dir {
+            true => script.push_str(&format!("mkdir -p {target}\ncp -R --preserve=mode,timestamps {source}/. {target}/\n", source = quote(source), target = quote(target))),
+            false => script.push_str(&format!("mkdir -p \"$(dirname {target})\"\ncp --preserve=mode,timestamps {source} {target}\n", source = quote(source), target = quote(target))),
+        }
+    }
+    let script_fn = format!("{}/{}", archive_dir, PLACEMENT_SCRIPT_FN);
+    fs::write(&script_fn, script).unwrap_or_else(|_| panic!("Error: could not write {}. Please check permissions", script_fn));
+}
+
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
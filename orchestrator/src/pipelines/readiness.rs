@@ -0,0 +1,73 @@
+// pipelines/readiness.rs
+//
+// Readiness checks (¥ READY in pipeline.run) are turned into small polling scripts,
+// run on the machines of the step since most targets (private IPs, local files) are
+// only reachable from inside the deployed system.
+
+use crate::utils::run_parser::{ReadinessCheck, CheckKind};
+use crate::pasir;
+
+// printed by the polling script once the check passes
+const READY_MARKER: &str = "TAAS_READY_OK";
+const POLL_INTERVAL_SECS: u64 = 2;
+
+/// Quotes a string for sh, so that it is passed as a single argument whatever it contains
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Returns the IP address of `host` as seen from `probe`, if `host` is the name of a machine.
+/// Anything else (IP address, DNS name) is used as is.
+fn resolve_host(host: &str, probe: &str, pasir_vms: &[pasir::machines::Vm], pasir_subnets: &[pasir::connections::Subnet]) -> String {
+    let host_idx = match pasir_vms.iter().position(|v| v.name == host) {
+        Some(idx) => idx,
+        None => return host.to_string(),
+    };
+    let probe_idx = pasir_vms.iter().position(|v| v.name == probe);
+
+    let host_subnets: Vec<&pasir::connections::Subnet> = pasir_subnets.iter().filter(|s| s.connected_vms.contains_key(&host_idx)).collect();
+    let shared = host_subnets.iter().find(|s| probe_idx.map(|p| s.connected_vms.contains_key(&p)).unwrap_or(false));
+    match (shared, host == probe) {
+        (Some(subnet), _) => subnet.connected_vms[&host_idx].to_string(),
+        (None, true) if !host_subnets.is_empty() => host_subnets[0].connected_vms[&host_idx].to_string(),
+        _ => panic!("Error: readiness check on machine '{}' targets machine '{}', but the two are not connected", probe, host),
+    }
+}
+
+/// Script that polls the check until it passes or times out
+fn check_script(check: &ReadinessCheck, probe: &str, user: &str, pasir_vms: &[pasir::machines::Vm], pasir_subnets: &[pasir::connections::Subnet]) -> String {
+    let condition = match check.kind {
+        CheckKind::Tcp => {
+            let pos = check.target.rfind(':').unwrap(); // format checked by the run parser
+            let host = resolve_host(&check.target[..pos], probe, pasir_vms, pasir_subnets);
+            format!("timeout 2 bash -c {} 2> /dev/null", shell_quote(&format!("exec 3<>/dev/tcp/{}/{}", host, &check.target[pos+1..])))
+        },
+        CheckKind::Http => format!("[ \"$(curl -s -o /dev/null -m 5 -w '%{{http_code}}' {})\" = \"200\" ]", shell_quote(&check.target)),
+        CheckKind::Cmd => format!("sh -c {} > /dev/null 2>&1", shell_quote(&check.target)),
+        CheckKind::File => format!("[ -e {} ]", shell_quote(&check.target)),
+    };
+
+    let mut script = String::new();
+    script.push_str(&format!("cd /home/{}\n", user));
+    script.push_str(&format!("end=$(( $(date +%s) + {} ))\n", check.timeout_secs));
+    script.push_str(&format!("until {}; do\n", condition));
+    script.push_str("    if [ $(date +%s) -ge $end ]; then exit 1; fi\n");
+    script.push_str(&format!("    sleep {}\n", POLL_INTERVAL_SECS));
+    script.push_str("done\n");
+    script.push_str(&format!("echo {}\n", READY_MARKER));
+    script
+}
+
+/// Polls the check from the given machine. Returns a description of the failure if it did not pass in time.
+pub fn run_check<F>(check: &ReadinessCheck, probe: &str, pasir_vms: &[pasir::machines::Vm], pasir_subnets: &[pasir::connections::Subnet], run_script_with_output: F) -> Result<(), String>
+    where F: Fn(&str, &str) -> String {
+
+    let user = &pasir_vms.iter().find(|v| v.name == probe).unwrap_or_else(|| panic!("Error: a readiness check in {} runs on machine '{}', but it is not declared in the system description.", crate::paths::RUN_STEPS_FN, probe)).auth.user;
+    let output = run_script_with_output(probe, &check_script(check, probe, user, pasir_vms, pasir_subnets));
+    if output.contains(READY_MARKER) {
+        Ok(())
+    }
+    else {
+        Err(format!("readiness check '{}' did not pass on machine '{}' within {}s", check, probe, check.timeout_secs))
+    }
+}
@@ -0,0 +1,214 @@
+// pipelines/scheduler.rs
+//
+// Runs the steps of a pipeline as a DAG: every (step, machine) pair is a task that starts
+// as soon as the steps it depends on are finished, instead of waiting for every machine
+// of the previous step. Steps without explicit dependencies keep the old sequential behaviour.
+// Tasks on the same machine still run one at a time, as the cloud provider only runs one command at once on a VM.
+
+use crate::utils::run_parser::{Step, StepType, DependencyTarget, ReadinessCheck};
+use crate::state::{self, Phase};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, Condvar};
+use std::time::{Duration, Instant};
+use std::thread;
+
+// pause between the start of two setup scripts, to limit load on the cloud provider
+const SETUP_PAUSE: Duration = Duration::from_millis(1000);
+
+enum Task {
+    /// Run the script of a step on a machine
+    Script(usize, String),
+    /// Poll the readiness checks of a step, once its scripts are done everywhere
+    Checks(usize),
+}
+
+#[derive(Default)]
+struct Progress {
+    done: HashSet<(usize, String)>,
+    /// Steps whose readiness checks have passed
+    ready: HashSet<usize>,
+    /// Description of the first task that failed
+    failed: Option<String>,
+}
+
+fn step_index(steps: &[Step], name: &str) -> usize {
+    steps.iter().position(|s| s.name == name).unwrap() // existence checked by the run parser
+}
+
+/// True when the scripts of the step are done on all of its machines. A step without
+/// any machine is done as soon as its own dependencies are.
+fn scripts_done(steps: &[Step], progress: &Progress, idx: usize) -> bool {
+    if steps[idx].scripts.is_empty() {
+        return dependencies_done(steps, progress, idx, None);
+    }
+    steps[idx].scripts.keys().all(|m| progress.done.contains(&(idx, m.clone())))
+}
+
+/// A step is finished once its scripts are done and its readiness checks have passed
+fn step_done(steps: &[Step], progress: &Progress, idx: usize) -> bool {
+    scripts_done(steps, progress, idx) && (steps[idx].checks.is_empty() || progress.ready.contains(&idx))
+}
+
+fn dependencies_done(steps: &[Step], progress: &Progress, idx: usize, machine: Option<&str>) -> bool {
+    match &steps[idx].depends_on {
+        None => idx == 0 || step_done(steps, progress, idx - 1),
+        Some(deps) => deps.iter().all(|dep| {
+            let dep_idx = step_index(steps, &dep.step);
+            match (&dep.target, machine) {
+                // per-machine dependencies only wait for the script, not for the readiness checks of the whole step
+                (DependencyTarget::Machine(m), _) => progress.done.contains(&(dep_idx, m.clone())),
+                // '@self' on a machine that does not take part in the other step waits for the whole step
+                (DependencyTarget::SameMachine, Some(m)) if steps[dep_idx].scripts.contains_key(m) => progress.done.contains(&(dep_idx, m.to_string())),
+                _ => step_done(steps, progress, dep_idx),
+            }
+        }),
+    }
+}
+
+/// Waits until the task can start. Returns false if another task failed in the meantime.
+fn wait_for(steps: &[Step], progress: &Mutex<Progress>, changed: &Condvar, task: &Task) -> bool {
+    let mut p = progress.lock().unwrap();
+    loop {
+        if p.failed.is_some() {
+            return false;
+        }
+        let can_start = match task {
+            Task::Script(idx, machine) => dependencies_done(steps, &p, *idx, Some(machine)),
+            Task::Checks(idx) => scripts_done(steps, &p, *idx),
+        };
+        if can_start {
+            return true;
+        }
+        p = changed.wait(p).unwrap();
+    }
+}
+
+/// Runs every (step, machine) task once its dependencies are satisfied, then the readiness checks of the step,
+/// skipping what is already recorded as done in the deployment state. Panics once all running tasks are over if any of them failed.
+///
+/// `run_check` polls one check from the given machine, and returns a description of the problem if it did not pass.
+pub fn run_steps<F, C>(steps: &[Step], run_task: F, run_check: C)
+    where F: Fn(usize, &str) + Sync,
+          C: Fn(&ReadinessCheck, &str) -> Result<(), String> + Sync {
+
+    let mut progress = Progress::default();
+    let mut tasks = Vec::new();
+    for (idx, step) in steps.iter().enumerate() {
+        let mut machines: Vec<&String> = step.scripts.keys().collect();
+        machines.sort();
+        for machine in machines {
+            if state::is_done(&Phase::Step(idx, machine.clone())) {
+                println_with_time!("Pipeline - Step {} ({}) already done on {}, skipping", step.name, step.step_type, machine);
+                progress.done.insert((idx, machine.clone()));
+            }
+            else {
+                tasks.push(Task::Script(idx, machine.clone()));
+            }
+        }
+        if !step.checks.is_empty() {
+            if state::is_done(&Phase::Ready(idx)) {
+                progress.ready.insert(idx);
+            }
+            else {
+                tasks.push(Task::Checks(idx));
+            }
+        }
+    }
+
+    let progress = Mutex::new(progress);
+    let changed = Condvar::new();
+    let last_setup_start = Mutex::new(Instant::now() - SETUP_PAUSE);
+    // held while a task uses a machine, readiness polls included
+    let machine_locks: HashMap<&str, Mutex<()>> = steps.iter()
+        .filter(|step| !step.step_type.runs_on_host())
+        .flat_map(|step| step.scripts.keys().chain(step.checks.iter().filter_map(|check| check.on.as_ref())))
+        .map(|machine| (machine.as_str(), Mutex::new(())))
+        .collect();
+
+    thread::scope(|scope| {
+        for task in tasks {
+            let (progress, changed, last_setup_start, machine_locks, run_task, run_check) = (&progress, &changed, &last_setup_start, &machine_locks, &run_task, &run_check);
+            scope.spawn(move || {
+                if !wait_for(steps, progress, changed, &task) {
+                    return;
+                }
+
+                match task {
+                    Task::Script(idx, machine) => {
+                        let step = &steps[idx];
+                        if let StepType::Setup = step.step_type {
+                            let mut last = last_setup_start.lock().unwrap();
+                            let elapsed = last.elapsed();
+                            if elapsed < SETUP_PAUSE {
+                                thread::sleep(SETUP_PAUSE - elapsed);
+                            }
+                            *last = Instant::now();
+                        }
+
+                        let _machine_lock = machine_locks.get(machine.as_str()).map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()));
+                        println_with_time!("Pipeline - Running step {} ({}) on {}", step.name, step.step_type, machine);
+                        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run_task(idx, &machine)));
+
+                        let mut p = progress.lock().unwrap();
+                        match result {
+                            Ok(_) => {
+                                state::mark_done(Phase::Step(idx, machine.clone()));
+                                p.done.insert((idx, machine));
+                                if step_done(steps, &p, idx) {
+                                    println_with_time!("Pipeline - Finished running step {} ({})", step.name, step.step_type);
+                                }
+                            },
+                            Err(_) => {
+                                if p.failed.is_none() {
+                                    p.failed = Some(format!("step '{}' failed on machine '{}'", step.name, machine));
+                                }
+                            },
+                        }
+                    },
+                    Task::Checks(idx) => {
+                        let step = &steps[idx];
+                        let mut result = Ok(());
+                        for check in &step.checks {
+                            let mut machines: Vec<&String> = match &check.on {
+                                Some(machine) => vec![machine],
+                                None => step.scripts.keys().collect(),
+                            };
+                            machines.sort();
+                            for machine in machines {
+                                let _machine_lock = machine_locks.get(machine.as_str()).map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()));
+                                println_with_time!("Pipeline - Waiting for readiness check '{}' of step {} on {}", check, step.name, machine);
+                                result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run_check(check, machine)))
+                                    .unwrap_or_else(|_| Err(format!("readiness check '{}' could not be run on machine '{}'", check, machine)));
+                                if result.is_err() {
+                                    break;
+                                }
+                            }
+                            if result.is_err() {
+                                break;
+                            }
+                        }
+
+                        let mut p = progress.lock().unwrap();
+                        match result {
+                            Ok(_) => {
+                                state::mark_done(Phase::Ready(idx));
+                                p.ready.insert(idx);
+                                println_with_time!("Pipeline - Step {} is ready", step.name);
+                            },
+                            Err(e) => {
+                                if p.failed.is_none() {
+                                    p.failed = Some(format!("{} (step '{}')", e, step.name));
+                                }
+                            },
+                        }
+                    },
+                }
+                changed.notify_all();
+            });
+        }
+    });
+
+    if let Some(failure) = progress.into_inner().unwrap().failed {
+        panic!("Error: {}, please look at the program output to see what went wrong. Scripts that were already running were allowed to finish.", failure);
+    }
+}
@@ -0,0 +1,224 @@
+// pipelines/steps.rs
+//
+// Execution of the built-in step kinds of pipeline.run (everything except SETUP and RUN,
+// which simply run their script). Files go through the orchestrator webserver, the same
+// way as the deployment data: the operator's host talks to it with scp, and the machines with curl.
+// Without webserver (direct distribution), uploads are pushed to the machines with scp, or run-commands without remote access.
+
+use crate::pipelines::TaasPipeline;
+use crate::utils::run_parser::Step;
+use crate::utils::global_config::SHELL;
+use crate::shell_tools;
+use crate::shell_tools::RunInfo;
+use crate::paths;
+use crate::report::protocol;
+use std::path::Path;
+use std::time::Duration;
+
+// printed by the machine scripts of the built-in steps once everything went well
+const STEP_OK_MARKER: &str = "TAAS_STEP_OK";
+// printed after a step script, to report how it went
+const EXIT_CODE_MARKER: &str = "TAAS_EXIT_CODE=";
+const DURATION_MARKER: &str = "TAAS_DURATION_MS=";
+// separates the end of the script output from the lines of the reporting protocol
+const PROTOCOL_MARKER: &str = "TAAS_PROTOCOL_LINES";
+// size of all the protocol lines printed by the script, to tell whether some were left out
+const PROTOCOL_BYTES_MARKER: &str = "TAAS_PROTOCOL_BYTES=";
+// how much of the end of the script output is sent back, Azure only keeps the last 4KB anyway
+const OUTPUT_TAIL_BYTES: usize = 2048;
+// room left for the reporting protocol lines, which are sent back wherever they are in the output
+const PROTOCOL_TAIL_BYTES: usize = 1536;
+
+/// Everything a built-in step needs to know about the deployment
+pub struct StepContext<'a> {
+    pub scenario: &'a str,
+    /// Public IP address of the orchestrator, for this host. None with the direct distribution, which has no webserver
+    pub orch_ip: Option<&'a str>,
+    /// Address the machines reach the webserver at, None without webserver
+    pub ws_host: Option<&'a str>,
+    /// Machine name -> user name
+    pub users: &'a std::collections::HashMap<String, String>,
+    /// Machine name -> how to reach it with scp, for the machines with remote access with the direct distribution
+    pub scp_targets: &'a std::collections::HashMap<String, crate::pipelines::distribution::ScpTarget>,
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_else(|| panic!("Error: '{}' in {} is not a valid file path", path, paths::RUN_STEPS_FN))
+}
+
+/// Runs a script on the machine from its user's home, and panics if it did not get to the end
+fn run_checked<P: TaasPipeline + ?Sized>(machine: &str, user: &str, script: &str, what: &str) {
+    let script = format!("cd /home/{}\nset -e\n{}\necho {}\n", user, script, STEP_OK_MARKER);
+    let output = P::run_script_with_output(machine, &script);
+    if !output.contains(STEP_OK_MARKER) {
+        panic!("Error: {} failed on machine '{}'. Output:\n{}", what, machine, output);
+    }
+}
+
+/// Value of the last line of the output starting with the given marker
+fn marker_value<T: std::str::FromStr>(output: &str, marker: &str) -> Option<T> {
+    output.lines().rev().find_map(|l| l.trim().strip_prefix(marker)).and_then(|v| v.trim().parse().ok())
+}
+
+/// Runs the script of a SETUP or RUN step (already on the machine as `filename`), as many times as requested,
+/// and records the duration, exit code and everything reported with the protocol of report::protocol for every execution
+pub fn script<P: TaasPipeline + ?Sized>(ctx: &StepContext, step: &Step, machine: &str, filename: &str, repeat: usize) {
+    let user = &ctx.users[machine];
+    let output_fn = format!("/tmp/taas_{}.out", filename);
+    let wrapper = format!("cd /home/{user}
+start=$(date +%s%N)
+sudo sh {file} > {out} 2>&1
+code=$?
+end=$(date +%s%N)
+tail -c {tail} {out}
+echo
+echo {protocol_marker}
+grep -E '{protocol}' {out} | tail -c {protocol_tail}
+echo
+echo {protocol_bytes_marker}$(grep -E '{protocol}' {out} | wc -c)
+echo {exit_marker}$code
+echo {duration_marker}$(( (end - start) / 1000000 ))
+",
+                        user = user, file = filename, out = output_fn, tail = OUTPUT_TAIL_BYTES, protocol_marker = PROTOCOL_MARKER, protocol = protocol::grep_pattern(), protocol_tail = PROTOCOL_TAIL_BYTES, protocol_bytes_marker = PROTOCOL_BYTES_MARKER, exit_marker = EXIT_CODE_MARKER, duration_marker = DURATION_MARKER);
+
+    let kind = step.step_type.to_string();
+    crate::report::reset(&step.name, &kind, machine);
+    let mut failed_iterations = Vec::new();
+    for iteration in 0..repeat {
+        let started = std::time::Instant::now();
+        let output = P::run_script_with_output(machine, &wrapper);
+        let exit_code: Option<i32> = marker_value(&output, EXIT_CODE_MARKER);
+        // fall back on the time measured from here, which includes the cloud provider overhead
+        let duration_ms = marker_value(&output, DURATION_MARKER).unwrap_or(started.elapsed().as_millis() as u64);
+        // only what comes after the marker, the same lines may also be in the end of the output
+        let reported = protocol::parse(output.rsplit_once(PROTOCOL_MARKER).map(|(_, lines)| lines).unwrap_or(""), machine);
+        if let Some(protocol_bytes) = marker_value::<usize>(&output, PROTOCOL_BYTES_MARKER).filter(|bytes| *bytes > PROTOCOL_TAIL_BYTES) {
+            println_with_time!("Pipeline - Warning: step {} printed {} bytes of protocol lines on {} (iteration {} of {}), only the last {} were kept. Earlier metrics, facts and assertions are missing from the report",
+                step.name, protocol_bytes, machine, iteration + 1, repeat, PROTOCOL_TAIL_BYTES);
+        }
+        for assertion in reported.assertions.iter().filter(|a| !a.passed) {
+            println_with_time!("Pipeline - Warning: assertion {} failed in step {} on {} (iteration {} of {}){}", assertion.name, step.name, machine, iteration + 1, repeat,
+                assertion.message.as_ref().map(|m| format!(": {}", m)).unwrap_or_default());
+        }
+
+        if exit_code != Some(0) {
+            let code = exit_code.map(|c| c.to_string()).unwrap_or_else(|| "unknown".to_string());
+            println_with_time!("Pipeline - Step {} exited with code {} on {} (iteration {} of {})", step.name, code, machine, iteration + 1, repeat);
+            failed_iterations.push(iteration + 1);
+        }
+        crate::report::record_iteration(&step.name, &kind, machine, crate::report::Iteration {
+            duration_ms,
+            exit_code,
+            metrics: reported.metrics,
+            facts: reported.facts,
+            assertions: reported.assertions,
+        }, reported.higher_is_better);
+    }
+
+    // every iteration is recorded first, so that the report shows how often the step fails
+    if !failed_iterations.is_empty() {
+        panic!("Error: step {} exited with a non-zero code on machine '{}' (iteration(s) {} of {})", step.name, machine,
+            failed_iterations.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", "), repeat);
+    }
+}
+
+/// Copies a file or folder of the scenario to the machine. Folders are sent as an archive and extracted in place.
+pub fn upload<P: TaasPipeline + ?Sized>(ctx: &StepContext, step_index: usize, machine: &str, local: &str, remote: &str) {
+    let local_path = format!("{}/{}/{}", paths::SCENARIO_PATH, ctx.scenario, local);
+    let is_dir = Path::new(&local_path).is_dir();
+    if !is_dir && !Path::new(&local_path).is_file() {
+        panic!("Error: {} uploads {}, which does not exist", paths::RUN_STEPS_FN, local_path);
+    }
+    let user = &ctx.users[machine];
+
+    // folders are sent as an archive
+    let archive_path = crate::state::run_path(&format!("{}.{}.{}.tgz", step_index, machine, file_name(local)));
+    let (sent_path, sent_name) = match is_dir {
+        true => {
+            shell_tools::run_command(&format!("tar czf {} -C {} .", archive_path, local_path), &SHELL.shell).panic_on_failure();
+            (archive_path.clone(), format!("{}.tgz", file_name(local)))
+        },
+        false => (local_path.clone(), file_name(local)),
+    };
+    // where the file first lands on the machine
+    let received = match is_dir {
+        true => "/tmp/taas_upload.tgz",
+        false => remote,
+    };
+
+    let fetch = match (ctx.orch_ip, ctx.ws_host) {
+        (Some(orch_ip), Some(ws_host)) => {
+            let ws_dir = format!("uploads/{}/{}", step_index, machine);
+            scp_to_orchestrator(orch_ip, &sent_path, &crate::orchestrator::remote_path(&ws_dir), &sent_name);
+            let url = format!("{}/{}/{}", crate::orchestrator::base_url(ws_host), ws_dir, sent_name);
+            let auth = crate::orchestrator::auth_args(&crate::state::webserver_token(machine));
+            match SHELL.download_tool.as_ref() {
+                "curl" => format!("curl -sf {} -o {} {}", auth, received, url),
+                _ => format!("wget -q {} -O {} {}", auth, received, url),
+            }
+        },
+        _ => {
+            crate::pipelines::distribution::push_file::<P>(machine, ctx.scp_targets.get(machine), &sent_path, received);
+            "true".to_string()
+        },
+    };
+    if is_dir {
+        std::fs::remove_file(&archive_path).ok();
+    }
+
+    let script = match is_dir {
+        true => format!("mkdir -p {remote}\n{fetch}\ntar xzf {received} -C {remote}\nrm -f {received}\nchown -R {user}:{user} {remote}", remote = remote, fetch = fetch, received = received, user = user),
+        false => format!("mkdir -p $(dirname {remote})\n{fetch}\nchown {user}:{user} {remote}", remote = remote, fetch = fetch, user = user),
+    };
+    run_checked::<P>(machine, user, &script, &format!("uploading {} to {}", local, remote));
+}
+
+fn scp_to_orchestrator(orch_ip: &str, local_fn: &str, ws_dir: &str, name: &str) {
+    shell_tools::run_command_try_again(&format!("ssh {} orch@{} \"mkdir -p {}\"", crate::orchestrator::ssh_args(), orch_ip, ws_dir), &SHELL.shell, 8, None).panic_on_failure();
+    shell_tools::run_command_try_again(&format!("scp {} {} orch@{}:~/{}/{}", crate::orchestrator::ssh_args(), local_fn, orch_ip, ws_dir, name), &SHELL.shell, 8, None).panic_on_failure();
+}
+
+/// Fetches a file or folder from the machine into runs/<run id>/downloads/[local/]<machine>/
+pub fn download<P: TaasPipeline + ?Sized>(ctx: &StepContext, machine: &str, remote: &str, local: &str) {
+    let user = &ctx.users[machine];
+    // checked when the distribution is chosen
+    let (orch_ip, ws_host) = ctx.orch_ip.zip(ctx.ws_host).expect("Error: DOWNLOAD steps need the orchestrator webserver");
+    // the webserver only stores single files, so everything is sent as an archive
+    let archive = format!("{}.tgz", file_name(remote));
+    let report_path = format!("machine_reports/{}/{}", machine, archive);
+    let url = format!("{}/{}", crate::orchestrator::base_url(ws_host), report_path);
+
+    let auth = crate::orchestrator::auth_args(&crate::state::webserver_token(machine));
+    let send = match SHELL.download_tool.as_ref() {
+        "curl" => format!("curl -sf {} -T /tmp/{} {}", auth, archive, url),
+        _ => format!("wget -q {} -O /dev/null --method=PUT --body-file=/tmp/{} {}", auth, archive, url),
+    };
+    let script = format!("tar czf /tmp/{archive} -C $(dirname {remote}) $(basename {remote})\n{send}\nrm -f /tmp/{archive}", archive = archive, remote = remote, send = send);
+    run_checked::<P>(machine, user, &script, &format!("sending {} back", remote));
+
+    let target_dir = match local {
+        "" => crate::state::run_path(&format!("{}/{}", paths::DOWNLOADS_DIR, machine)),
+        local => crate::state::run_path(&format!("{}/{}/{}", paths::DOWNLOADS_DIR, local, machine)),
+    };
+    std::fs::create_dir_all(&target_dir).unwrap_or_else(|_| panic!("Error: could not create {}. Please check permissions", target_dir));
+    shell_tools::run_command_try_again(&format!("scp {} orch@{}:~/{} {}/", crate::orchestrator::ssh_args(), orch_ip, crate::orchestrator::remote_path(&report_path), target_dir), &SHELL.shell, 8, None).panic_on_failure();
+    shell_tools::run_command(&format!("tar xzf {dir}/{archive} -C {dir} && rm {dir}/{archive}", dir = target_dir, archive = archive), &SHELL.shell).panic_on_failure();
+    println_with_time!("Pipeline - Downloaded {} from {} into {}", remote, machine, target_dir);
+}
+
+/// Restarts the machine. The cloud-specific restart only returns once the machine can run scripts again.
+pub fn reboot<P: TaasPipeline + ?Sized>(machine: &str) {
+    P::restart_machine(machine);
+}
+
+pub fn wait(seconds: u64) {
+    std::thread::sleep(Duration::from_secs(seconds));
+}
+
+/// Runs the script on the operator's host, with the replacements available to the post-deployment script
+pub fn local(script: &str) {
+    let exit_code = crate::post_deployment::run_from(script, paths::RUN_STEPS_FN).exit_code();
+    if exit_code != 0 {
+        panic!("Error: a local step of {} exited with code {}", paths::RUN_STEPS_FN, exit_code);
+    }
+}
@@ -19,17 +19,39 @@ pub fn add_global_replacement(from: &str, to: &str) {
     GLOBAL_REPL_MAP.lock().unwrap().insert(from, to);
 }
 
-pub fn replace(script: &str) -> String {
+/// Adds the user and password of a machine to the global replacement map (machines/<name>/user, .../password...)
+pub fn add_machine_credentials(machine_name: &str, user: &str, password: &str) {
+    add_global_replacement(&format!("machines/{}/user", machine_name), user);
+    add_global_replacement(&format!("machines/{}/username", machine_name), user);
+    add_global_replacement(&format!("machines/{}/pass", machine_name), password);
+    add_global_replacement(&format!("machines/{}/password", machine_name), password);
+}
+
+/// Adds the public hostname of a machine to the global replacement map (machines/<name>/public_ip...)
+pub fn add_machine_public_host(machine_name: &str, public_hostname: &str) {
+    add_global_replacement(&format!("machines/{}/public_ip", machine_name), public_hostname);
+    add_global_replacement(&format!("machines/{}/public_ip_address", machine_name), public_hostname);
+    add_global_replacement(&format!("machines/{}/public_host", machine_name), public_hostname);
+    add_global_replacement(&format!("machines/{}/public_hostname", machine_name), public_hostname);
+}
 
+/// The global replacement map, as the post-deployment script sees it
+pub fn global_replacements() -> HashMap<String, String> {
     add_global_replacement("SSH_OPTIONS", &crate::utils::global_config::SSH.custom_args);
     add_global_replacement("SSH_CONFIG", &crate::utils::global_config::SSH.custom_args);
+    GLOBAL_REPL_MAP.lock().unwrap().clone()
+}
 
-    let ret = replace::replace(script, &*GLOBAL_REPL_MAP.lock().unwrap(), replace::ReplaceFailPolicy::Warn, Some("post_deployment.sh".to_string()));
-
-    ret
+/// Replaces the global values in the script. `source` is the file the script comes from, used in warnings
+pub fn replace(script: &str, source: &str) -> String {
+    replace::replace(script, &global_replacements(), replace::run_fail_policy(), Some(source.to_string()))
 }
 
 pub fn run(script: &str) -> crate::shell_tools::RunSummary {
-    let script = replace(script);
+    run_from(script, "post_deployment.sh")
+}
+
+pub fn run_from(script: &str, source: &str) -> crate::shell_tools::RunSummary {
+    let script = replace(script, source);
     shell_tools::run_command_interactive(&script, &SHELL.shell)
 }
\ No newline at end of file
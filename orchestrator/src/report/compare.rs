@@ -0,0 +1,170 @@
+// report/compare.rs
+//
+// Comparison of the report of a run with the one of a baseline run, step by step and machine by machine:
+// median duration, pass ratio (i.e. exit codes) and median of every metric. A change for the worse
+// beyond the threshold is a regression. Any drop of the pass ratio is a regression, whatever the threshold.
+
+use super::{RunReport, MachineReport};
+
+/// Relative change from `base` to `current` in percent, oriented so that a positive value is worse
+fn worsening_percent(base: f64, current: f64, higher_is_better: bool) -> f64 {
+    let change = match higher_is_better {
+        true => base - current,
+        false => current - base,
+    };
+    match base == 0.0 {
+        true if change > 0.0 => f64::INFINITY,
+        true => 0.0,
+        false => change / base.abs() * 100.0,
+    }
+}
+
+/// Prints one compared value, returns true if it is a regression
+fn compare_value(what: &str, base: f64, current: f64, higher_is_better: bool, threshold_percent: f64) -> bool {
+    let worsening = worsening_percent(base, current, higher_is_better);
+    let regression = worsening > threshold_percent;
+    let change = match base == 0.0 {
+        true => "n/a".to_string(),
+        false => format!("{:+.1}%", (current - base) / base.abs() * 100.0),
+    };
+    println_with_time!("Compare - {}: {} -> {} ({}){}", what, base, current, change, if regression { " REGRESSION" } else { "" });
+    regression
+}
+
+fn compare_machine(what: &str, base: &MachineReport, current: &MachineReport, higher_is_better: &dyn Fn(&str) -> bool, threshold_percent: f64) -> usize {
+    let mut regressions = 0;
+    if let (Some(base_stats), Some(current_stats)) = (&base.stats, &current.stats) {
+        if compare_value(&format!("{} median duration (ms)", what), base_stats.median_ms as f64, current_stats.median_ms as f64, false, threshold_percent) {
+            regressions += 1;
+        }
+        let pass_ratio_dropped = current_stats.pass_ratio < base_stats.pass_ratio;
+        println_with_time!("Compare - {} passed: {}/{} -> {}/{}{}", what,
+            base_stats.passed, base_stats.passed + base_stats.failed, current_stats.passed, current_stats.passed + current_stats.failed,
+            if pass_ratio_dropped { " REGRESSION" } else { "" });
+        if pass_ratio_dropped {
+            regressions += 1;
+        }
+    }
+
+    let base_metrics = base.metric_names();
+    let current_metrics = current.metric_names();
+    for metric in base_metrics.union(&current_metrics) {
+        match (base.metric_median(metric), current.metric_median(metric)) {
+            (Some(b), Some(c)) => {
+                if compare_value(&format!("{} metric {}", what, metric), b, c, higher_is_better(metric), threshold_percent) {
+                    regressions += 1;
+                }
+            },
+            (Some(_), None) => println_with_time!("Compare - Warning: {} metric {} is missing from the compared run", what, metric),
+            (None, _) => println_with_time!("Compare - {} metric {} is new, nothing to compare with", what, metric),
+        }
+    }
+    regressions
+}
+
+/// Compares the report of a run with the one of a baseline run, prints the differences, and returns the number of regressions
+pub fn compare(baseline: &RunReport, current: &RunReport, threshold_percent: f64) -> usize {
+    println_with_time!("Compare - Run {} against baseline {}, regression threshold {}%", current.run_id, baseline.run_id, threshold_percent);
+    if baseline.scenario != current.scenario {
+        println_with_time!("Compare - Warning: the runs are of different scenarios ({} and {})", baseline.scenario, current.scenario);
+    }
+    let higher_is_better = |metric: &str| current.higher_is_better.contains(metric) || baseline.higher_is_better.contains(metric);
+
+    let mut regressions = 0;
+    for base_step in &baseline.steps {
+        let current_step = match current.steps.iter().find(|s| s.name == base_step.name) {
+            Some(step) => step,
+            None => {
+                println_with_time!("Compare - Warning: step {} is missing from run {}", base_step.name, current.run_id);
+                continue;
+            },
+        };
+        for (machine, base_machine) in &base_step.machines {
+            match current_step.machines.get(machine) {
+                Some(current_machine) => regressions += compare_machine(&format!("Step {} on {}", base_step.name, machine), base_machine, current_machine, &higher_is_better, threshold_percent),
+                None => println_with_time!("Compare - Warning: step {} did not run on {} in run {}", base_step.name, machine, current.run_id),
+            }
+        }
+    }
+    for current_step in current.steps.iter().filter(|s| !baseline.steps.iter().any(|b| b.name == s.name)) {
+        println_with_time!("Compare - Step {} is new, nothing to compare with", current_step.name);
+    }
+
+    match regressions {
+        0 => println_with_time!("Compare - No regression beyond {}%", threshold_percent),
+        n => println_with_time!("Compare - {} regression(s) beyond {}%", n, threshold_percent),
+    }
+    regressions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{Iteration, Stats, StepReport};
+
+    /// Duration in ms, exit code and metrics of an iteration
+    type TestIteration<'a> = (u64, i32, &'a [(&'a str, f64)]);
+
+    fn machine(iterations: &[TestIteration]) -> MachineReport {
+        let iterations: Vec<Iteration> = iterations.iter().map(|(duration_ms, exit_code, metrics)| Iteration {
+            duration_ms: *duration_ms,
+            exit_code: Some(*exit_code),
+            metrics: metrics.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            ..Default::default()
+        }).collect();
+        MachineReport { stats: Stats::from_iterations(&iterations), iterations }
+    }
+
+    fn run(run_id: &str, steps: Vec<(&str, MachineReport)>) -> RunReport {
+        RunReport {
+            run_id: run_id.to_string(),
+            scenario: "bench".to_string(),
+            steps: steps.into_iter().map(|(name, report)| StepReport {
+                name: name.to_string(),
+                kind: "script".to_string(),
+                machines: std::iter::once(("db".to_string(), report)).collect(),
+            }).collect(),
+            higher_is_better: std::iter::once("tps".to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn worsening() {
+        assert_eq!(worsening_percent(100.0, 120.0, false), 20.0);
+        assert_eq!(worsening_percent(100.0, 80.0, false), -20.0);
+        assert_eq!(worsening_percent(100.0, 80.0, true), 20.0);
+        assert_eq!(worsening_percent(0.0, 1.0, false), f64::INFINITY);
+        assert_eq!(worsening_percent(0.0, 0.0, false), 0.0);
+    }
+
+    #[test]
+    fn changes_within_the_threshold() {
+        let baseline = run("base", vec![("bench", machine(&[(1000, 0, &[("tps", 500.0), ("latency", 10.0)])]))]);
+        let current = run("new", vec![("bench", machine(&[(1040, 0, &[("tps", 490.0), ("latency", 10.4)])]))]);
+        assert_eq!(compare(&baseline, &current, 5.0), 0);
+    }
+
+    #[test]
+    fn regressions() {
+        let baseline = run("base", vec![("bench", machine(&[(1000, 0, &[("tps", 500.0), ("latency", 10.0)]), (1000, 0, &[])]))]);
+        // slower, fewer tps, higher latency and one failed iteration out of two
+        let current = run("new", vec![("bench", machine(&[(1200, 0, &[("tps", 400.0), ("latency", 12.0)]), (1200, 1, &[])]))]);
+        assert_eq!(compare(&baseline, &current, 5.0), 4);
+        // improvements are never regressions
+        assert_eq!(compare(&current, &baseline, 5.0), 0);
+    }
+
+    #[test]
+    fn pass_ratio_ignores_the_threshold() {
+        let baseline = run("base", vec![("bench", machine(&[(1000, 0, &[]), (1000, 0, &[])]))]);
+        let current = run("new", vec![("bench", machine(&[(1000, 0, &[]), (1000, 2, &[])]))]);
+        assert_eq!(compare(&baseline, &current, 1000.0), 1);
+    }
+
+    #[test]
+    fn missing_and_new_steps_are_not_regressions() {
+        let baseline = run("base", vec![("old", machine(&[(1000, 0, &[("tps", 500.0)])]))]);
+        let current = run("new", vec![("new", machine(&[(5000, 1, &[])]))]);
+        assert_eq!(compare(&baseline, &current, 5.0), 0);
+    }
+}
@@ -0,0 +1,202 @@
+// report/mod.rs
+//
+// Collects what happened on the machines during the pipeline steps (duration, exit code and metrics
+// of every execution) into the run report, written to runs/<run id>/run_report.yml every
+// time something is recorded, so that it is complete even if the run fails half-way.
+
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::fs;
+use std::path::Path;
+use crate::paths;
+
+pub mod compare;
+pub mod protocol;
+
+/// One execution of a step script on a machine
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Iteration {
+    pub duration_ms: u64,
+    /// None if the script did not report its exit code (e.g. it was killed)
+    pub exit_code: Option<i32>,
+    /// Numeric values printed by the script with 'TAAS_METRIC name=value'
+    #[serde(default)]
+    pub metrics: BTreeMap<String, f64>,
+    /// Values printed by the script with 'TAAS_FACT key=value'
+    #[serde(default)]
+    pub facts: BTreeMap<String, String>,
+    /// Checks reported by the script with 'TAAS_ASSERT name pass|fail [message]'
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+}
+
+impl Iteration {
+    /// An iteration passes if the script exited with code 0 and none of its assertions failed
+    pub fn passed(&self) -> bool {
+        self.exit_code == Some(0) && self.assertions.iter().all(|a| a.passed)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Assertion {
+    pub name: String,
+    pub passed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Aggregated values over all the iterations of a step on a machine
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Stats {
+    pub min_ms: u64,
+    pub median_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+    pub passed: usize,
+    pub failed: usize,
+    /// Share of iterations that passed, between 0 and 1. Anything in between means the step is flaky.
+    pub pass_ratio: f64,
+}
+
+impl Stats {
+    fn from_iterations(iterations: &[Iteration]) -> Option<Self> {
+        if iterations.is_empty() {
+            return None;
+        }
+        let mut durations: Vec<u64> = iterations.iter().map(|i| i.duration_ms).collect();
+        durations.sort();
+        let passed = iterations.iter().filter(|i| i.passed()).count();
+        Some(Stats {
+            min_ms: durations[0],
+            median_ms: percentile(&durations, 50),
+            p95_ms: percentile(&durations, 95),
+            max_ms: durations[durations.len() - 1],
+            passed,
+            failed: iterations.len() - passed,
+            pass_ratio: passed as f64 / iterations.len() as f64,
+        })
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile<T: Copy>(sorted: &[T], percent: usize) -> T {
+    let rank = (percent * sorted.len()).div_ceil(100);
+    sorted[rank.max(1) - 1]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MachineReport {
+    pub iterations: Vec<Iteration>,
+    pub stats: Option<Stats>,
+}
+
+impl MachineReport {
+    /// Median of a metric over all iterations that reported it
+    pub fn metric_median(&self, name: &str) -> Option<f64> {
+        let mut values: Vec<f64> = self.iterations.iter().filter_map(|i| i.metrics.get(name).cloned()).collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Some(percentile(&values, 50))
+    }
+
+    pub fn metric_names(&self) -> BTreeSet<String> {
+        self.iterations.iter().flat_map(|i| i.metrics.keys().cloned()).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StepReport {
+    pub name: String,
+    pub kind: String,
+    pub machines: BTreeMap<String, MachineReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RunReport {
+    pub run_id: String,
+    pub scenario: String,
+    pub steps: Vec<StepReport>,
+    /// Metrics for which a higher value is better ('TAAS_METRIC name=value higher'), lower is better for the others
+    #[serde(default)]
+    pub higher_is_better: BTreeSet<String>,
+}
+
+lazy_static! {
+    static ref REPORT: Mutex<RunReport> = Mutex::new(RunReport::default());
+}
+
+fn report_fn(run_id: &str) -> String {
+    format!("{}/{}/{}", paths::RUNS_PATH, run_id, paths::RUN_REPORT_FN)
+}
+
+/// Loads the report of the given run
+pub fn load(run_id: &str) -> RunReport {
+    crate::state::check_run_id(run_id);
+    let report_fn = report_fn(run_id);
+    let raw = fs::read_to_string(&report_fn).unwrap_or_else(|_| panic!("Error: could not open {}. Make sure run {} exists and got to its pipeline steps.", report_fn, run_id));
+    serde_yaml::from_str(&raw).unwrap_or_else(|e| panic!("Error: {} is not a valid run report. Issue:\n{}\n", report_fn, e))
+}
+
+/// Starts the report of the current run, keeping what was already recorded if the run is resumed
+pub fn start(scenario: &str) {
+    let run_id = crate::state::run_id();
+    let mut report = REPORT.lock().unwrap();
+    *report = match Path::new(&report_fn(&run_id)).exists() {
+        true => load(&run_id),
+        false => RunReport { run_id, scenario: scenario.to_string(), ..RunReport::default() },
+    };
+    save(&report);
+}
+
+fn machine_report<'a>(report: &'a mut RunReport, step: &str, kind: &str, machine: &str) -> &'a mut MachineReport {
+    let pos = match report.steps.iter().position(|s| s.name == step) {
+        Some(pos) => pos,
+        None => {
+            report.steps.push(StepReport { name: step.to_string(), kind: kind.to_string(), machines: BTreeMap::new() });
+            report.steps.len() - 1
+        },
+    };
+    report.steps[pos].machines.entry(machine.to_string()).or_default()
+}
+
+/// Forgets the iterations of a step on a machine, for when it is started again
+pub fn reset(step: &str, kind: &str, machine: &str) {
+    let mut report = REPORT.lock().unwrap();
+    *machine_report(&mut report, step, kind, machine) = MachineReport::default();
+    save(&report);
+}
+
+/// Records an execution. `higher_is_better` lists the metrics of the iteration for which higher values are better.
+pub fn record_iteration(step: &str, kind: &str, machine: &str, iteration: Iteration, higher_is_better: Vec<String>) {
+    let mut report = REPORT.lock().unwrap();
+    report.higher_is_better.extend(higher_is_better);
+    let machine_report = machine_report(&mut report, step, kind, machine);
+    machine_report.iterations.push(iteration);
+    machine_report.stats = Stats::from_iterations(&machine_report.iterations);
+    save(&report);
+}
+
+/// Prints the statistics of every step that ran more than once, or that did not always pass
+pub fn print_summary() {
+    let report = REPORT.lock().unwrap();
+    for step in &report.steps {
+        for (machine, machine_report) in &step.machines {
+            if let Some(stats) = &machine_report.stats {
+                if machine_report.iterations.len() > 1 || stats.failed > 0 {
+                    println_with_time!("Report - Step {} on {}: {} runs, min {}ms, median {}ms, p95 {}ms, max {}ms, {}/{} passed",
+                        step.name, machine, machine_report.iterations.len(), stats.min_ms, stats.median_ms, stats.p95_ms, stats.max_ms, stats.passed, machine_report.iterations.len());
+                }
+            }
+        }
+    }
+    println_with_time!("Report - Written to {}", report_fn(&report.run_id));
+}
+
+fn save(report: &RunReport) {
+    let yml = serde_yaml::to_string(report).expect("Error: could not serialise the run report");
+    let report_fn = report_fn(&report.run_id);
+    fs::write(&report_fn, yml).unwrap_or_else(|_| panic!("Error: could not write {}. Please check permissions", report_fn));
+}
@@ -0,0 +1,92 @@
+// report/protocol.rs
+//
+// What step scripts can report back, by printing lines starting with a marker on stdout:
+//   TAAS_METRIC <name>=<number> [higher|lower]   numeric value, compared between runs (lower is better by default)
+//   TAAS_FACT <key>=<value>                      free text, e.g. a software version
+//   TAAS_ASSERT <name> pass|fail [message]       check done by the script, a failure fails the iteration
+// The lines can be anywhere in the output, the last one wins if a metric or fact is printed twice.
+
+use std::collections::BTreeMap;
+use super::Assertion;
+
+pub const METRIC_MARKER: &str = "TAAS_METRIC ";
+pub const FACT_MARKER: &str = "TAAS_FACT ";
+pub const ASSERT_MARKER: &str = "TAAS_ASSERT ";
+
+/// Everything reported by one execution of a script
+#[derive(Default)]
+pub struct Reported {
+    pub metrics: BTreeMap<String, f64>,
+    /// Metrics for which a higher value is better
+    pub higher_is_better: Vec<String>,
+    pub facts: BTreeMap<String, String>,
+    pub assertions: Vec<Assertion>,
+}
+
+/// Extended regex matching every line of the protocol, for grep on the machines
+pub fn grep_pattern() -> String {
+    format!("^({}|{}|{})", METRIC_MARKER, FACT_MARKER, ASSERT_MARKER)
+}
+
+fn parse_metric(line: &str, reported: &mut Reported) -> bool {
+    let mut words = line.split_whitespace();
+    let parsed = words.next()
+        .and_then(|w| w.split_once('='))
+        .and_then(|(name, value)| value.parse::<f64>().ok().filter(|v| v.is_finite()).map(|v| (name.to_string(), v)));
+    match (parsed, words.next(), words.next()) {
+        (Some((name, value)), direction @ (None | Some("lower") | Some("higher")), None) => {
+            if direction == Some("higher") {
+                reported.higher_is_better.push(name.clone());
+            }
+            reported.metrics.insert(name, value);
+            true
+        },
+        _ => false,
+    }
+}
+
+fn parse_fact(line: &str, reported: &mut Reported) -> bool {
+    match line.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() && !key.trim().contains(char::is_whitespace) => {
+            reported.facts.insert(key.trim().to_string(), value.trim().to_string());
+            true
+        },
+        _ => false,
+    }
+}
+
+fn parse_assertion(line: &str, reported: &mut Reported) -> bool {
+    let mut words = line.splitn(3, char::is_whitespace);
+    let name = words.next().unwrap_or("");
+    let passed = match words.next() {
+        Some("pass") => true,
+        Some("fail") => false,
+        _ => return false,
+    };
+    let message = words.next().map(|m| m.trim().to_string()).filter(|m| !m.is_empty());
+    reported.assertions.push(Assertion { name: name.to_string(), passed, message });
+    true
+}
+
+/// Collects the protocol lines of a script output. Invalid lines are reported as warnings and ignored.
+pub fn parse(output: &str, machine: &str) -> Reported {
+    let mut reported = Reported::default();
+    for line in output.lines().map(|l| l.trim()) {
+        let (valid, expected) = if let Some(rest) = line.strip_prefix(METRIC_MARKER) {
+            (parse_metric(rest.trim(), &mut reported), "name=number [higher|lower]")
+        }
+        else if let Some(rest) = line.strip_prefix(FACT_MARKER) {
+            (parse_fact(rest, &mut reported), "key=value")
+        }
+        else if let Some(rest) = line.strip_prefix(ASSERT_MARKER) {
+            (parse_assertion(rest.trim(), &mut reported), "name pass|fail [message]")
+        }
+        else {
+            continue;
+        };
+        if !valid {
+            println_with_time!("Pipeline - Warning: ignoring invalid line '{}' on {}, expected '{}'", line, machine, expected);
+        }
+    }
+    reported
+}
@@ -7,6 +7,7 @@ pub struct AzureScriptPush {
 impl super::ScriptPush for AzureScriptPush {
 
     fn new() -> Self {
+        crate::state::load(None);
         AzureScriptPush {vms: Self::load_last_deployment_summary(), replace: Self::load_last_deployment_replacements()}
     }
 
@@ -8,38 +8,11 @@ pub mod azure;
 
 pub trait ScriptPush {
 
+    /// Replaces the files of the replace/ folder (and its subfolders) of a script setup into repl_temp/, next to it
     fn prepare_replaced(&self, vm_name: &str, repl_path: &std::path::Path) {
-        //TODO: replace this with some kind of do_for_all_files() function
-        let all_content = repl_path.read_dir();
-        let _all_content = all_content.map( |c|
-            for elem in c {
-                elem.map( |e|
-                    e.file_type().map( |tpe|
-                    
-                        match tpe.is_file() {
-                            true => {
-
-                                // file name manipulation
-                                let mut mut_path = repl_path.to_path_buf();
-                                let inn: String = e.path().to_string_lossy().to_string();
-                                mut_path.pop();
-                                mut_path.push("repl_temp");
-                                std::fs::create_dir_all(&mut_path).expect(&format!("Error: could not create directory for temporary replaced files '{}'. Check user permissions.", mut_path.to_string_lossy().to_string()));
-                                mut_path.push(e.file_name());
-                                let out: String = mut_path.to_string_lossy().to_string();
-
-                                // recover replacement map
-                                let repl_data = self.get_vm_replacements(vm_name);
-                                println!("{}", out);
-                                
-                                crate::utils::replace::copy_and_replace_v2(&inn, &out, &repl_data.replacements, crate::utils::replace::ReplaceFailPolicy::Warn)
-                            }
-                            false => {}
-                        }
-                    )
-                ).unwrap().unwrap();
-            }
-        );
+        let out_path = repl_path.with_file_name("repl_temp");
+        let repl_data = self.get_vm_replacements(vm_name);
+        crate::utils::replace::copy_and_replace_dir(&repl_path.to_string_lossy(), &out_path.to_string_lossy(), &repl_data.replacements, crate::utils::replace::ReplaceFailPolicy::Warn);
     }
 
     fn new() -> Self;
@@ -142,28 +115,31 @@ pub trait ScriptPush {
         //first, delete push_scripts/'deployment_name' in remote machine (as sudo) (in case it exists)
         //then, copy the 'deployment_name' to the machine
         //then, run the script
+        let ssh_args = vm_summary.ssh_args();
+        let sudo = vm_summary.sudo();
 
-        shell_tools::run_command(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null {}@{} \"cd ~; echo {} | sudo -S rm -rf push_scripts/{}\"", SSH.custom_args, vm_summary.username, vm_summary.hostname, vm_summary.password, deployment_name), &SHELL.shell).panic_on_failure();
-        shell_tools::run_command(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null {}@{} \"cd ~; mkdir -p push_scripts/{}\"", SSH.custom_args, vm_summary.username, vm_summary.hostname, deployment_name), &SHELL.shell).panic_on_failure();
-        shell_tools::run_command(&format!("scp {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null push_scripts/{}/run.sh {}@{}:~/push_scripts/{}/", SSH.custom_args, deployment_name, vm_summary.username, vm_summary.hostname, deployment_name), &SHELL.shell).panic_on_failure();
+        shell_tools::run_command(&format!("ssh {} {}@{} \"cd ~; {} rm -rf push_scripts/{}\"", ssh_args, vm_summary.username, vm_summary.hostname, sudo, deployment_name), &SHELL.shell).panic_on_failure();
+        shell_tools::run_command(&format!("ssh {} {}@{} \"cd ~; mkdir -p push_scripts/{}\"", ssh_args, vm_summary.username, vm_summary.hostname, deployment_name), &SHELL.shell).panic_on_failure();
+        shell_tools::run_command(&format!("scp {} push_scripts/{}/run.sh {}@{}:~/push_scripts/{}/", ssh_args, deployment_name, vm_summary.username, vm_summary.hostname, deployment_name), &SHELL.shell).panic_on_failure();
         
         let data_str = format!("push_scripts/{}/data", deployment_name);
         let data_path = std::path::Path::new(&data_str);
         if data_path.is_dir() && data_path.read_dir().unwrap().count() > 0 {
-            shell_tools::run_command(&format!("scp -r {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null push_scripts/{}/data/* {}@{}:~/", SSH.custom_args, deployment_name, vm_summary.username, vm_summary.hostname), &SHELL.shell).panic_on_failure();
+            shell_tools::run_command(&format!("scp -rp {} push_scripts/{}/data/* {}@{}:~/", ssh_args, deployment_name, vm_summary.username, vm_summary.hostname), &SHELL.shell).panic_on_failure();
         }
         let repl_str = format!("push_scripts/{}/replace", deployment_name);
         let repl_path = std::path::Path::new(&repl_str);
         if repl_path.is_dir() && repl_path.read_dir().unwrap().count() > 0 {
             self.prepare_replaced(&vm_summary.name, &repl_path);
-            shell_tools::run_command(&format!("scp {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null push_scripts/{}/repl_temp/* {}@{}:~/", SSH.custom_args, deployment_name, vm_summary.username, vm_summary.hostname), &SHELL.shell).panic_on_failure();
+            shell_tools::run_command(&format!("scp -rp {} push_scripts/{}/repl_temp/* {}@{}:~/", ssh_args, deployment_name, vm_summary.username, vm_summary.hostname), &SHELL.shell).panic_on_failure();
             shell_tools::run_command_no_output(&format!("rm -rf push_scripts/{}/repl_temp", deployment_name), &SHELL.shell);
         }
-        shell_tools::run_command(&format!("ssh {} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null {}@{} \"cd ~; echo {} | sudo -S sh ~/push_scripts/{}/run.sh\"", SSH.custom_args, vm_summary.username, vm_summary.hostname, vm_summary.password, deployment_name), &SHELL.shell).panic_on_failure();
+        shell_tools::run_command(&format!("ssh {} {}@{} \"cd ~; {} sh ~/push_scripts/{}/run.sh\"", ssh_args, vm_summary.username, vm_summary.hostname, sudo, deployment_name), &SHELL.shell).panic_on_failure();
     }
 
     fn load_last_deployment_summary() -> Vec<VmSummary> {
-        let last_deployment_summary = std::fs::read_to_string("last_deployment_summary.yml").expect("Error: could not open last_deployment_summary.yml. Make sure you have run the deployment first, as well as the file permissions."); //TODO: don't use hard-coded value
+        let summary_fn = crate::state::run_path(crate::paths::DEPLOYMENT_SUMMARY_FN);
+        let last_deployment_summary = std::fs::read_to_string(&summary_fn).unwrap_or_else(|_| panic!("Error: could not open {}. Make sure you have run the deployment first, as well as the file permissions.", summary_fn));
         let parse: Result<Vec<VmSummary>, _> = serde_yaml::from_str(&last_deployment_summary); 
 
         //TODO: handle unwrap
@@ -171,7 +147,8 @@ pub trait ScriptPush {
     }
 
     fn load_last_deployment_replacements() -> Vec<ReplData> {
-        let last_deployment_replacements = std::fs::read_to_string("last_deployment_replacements.yml").expect("Error: could not open last_deployment_replacements.yml. Make sure you have run the deployment first, as well as the file permissions."); //TODO: don't use hard-coded value
+        let replacements_fn = crate::state::run_path(crate::paths::DEPLOYMENT_REPLACEMENTS_FN);
+        let last_deployment_replacements = std::fs::read_to_string(&replacements_fn).unwrap_or_else(|_| panic!("Error: could not open {}. Make sure you have run the deployment first, as well as the file permissions.", replacements_fn));
         let parse: Result<Vec<ReplData>, _> = serde_yaml::from_str(&last_deployment_replacements);
 
         //TODO: handle unwrap
@@ -185,6 +162,27 @@ pub struct VmSummary {
     hostname: String,
     username: String,
     password: String,
+    /// Private key to log in with, absent from the summaries of runs older than ssh keys
+    #[serde(default)]
+    ssh_key: Option<String>,
+}
+
+impl VmSummary {
+    /// Options of ssh and scp (without the user and host) to log in to the machine
+    fn ssh_args(&self) -> String {
+        match &self.ssh_key {
+            Some(key) => crate::utils::ssh_key::ssh_args(key),
+            None => format!("{} -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null", SSH.custom_args),
+        }
+    }
+
+    /// Prefix running a command as root on the machine, only machines logged in to with a password need it
+    fn sudo(&self) -> String {
+        match &self.ssh_key {
+            Some(_) => "sudo".to_string(),
+            None => format!("echo {} | sudo -S", self.password),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -0,0 +1,268 @@
+// serve/mod.rs
+//
+// The file server of the orchestrator VM, started with `orchestrator serve`. Machines download
+// their deployment files and the common data with GET, and send files back with PUT, which is
+// only allowed into machine_reports/<machine>/. A minimal HTTP/1.1 implementation is enough
+// for curl and wget, every connection is closed after one request.
+//
+// With --tokens, every request needs an 'Authorization: Bearer <token>' header, and a machine
+// can only reach its own files (<machine>/, uploads/<step>/<machine>/, machine_reports/<machine>/)
+// and the shared ones (common_data/, barriers/). The tokens file has one '<machine> <token>' line per
+// machine, and is read again for every request so that machines can be added while serving.
+//
+// A shared orchestrator serves several runs, each in its own runs/<run id>/ folder with its own
+// tokens file: the rules above then apply within the folder of the run, whatever --tokens says.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 8000;
+const DEFAULT_THREADS: usize = 32;
+// the only folder machines can upload to
+const UPLOAD_DIR: &str = "machine_reports";
+const MAX_HEADER_LINES: usize = 100;
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+const USAGE: &str = "Usage: orchestrator serve [--root <folder>] [--port <port>] [--threads <count>] [--tokens <file>]";
+
+struct Request {
+    method: String,
+    path: String,
+    token: Option<String>,
+    content_length: Option<u64>,
+    expect_continue: bool,
+    chunked: bool,
+}
+
+struct ServeOptions {
+    root: PathBuf,
+    port: u16,
+    threads: usize,
+    tokens: Option<PathBuf>,
+}
+
+fn parse_options(args: &[String]) -> ServeOptions {
+    let mut options = ServeOptions { root: PathBuf::from("."), port: DEFAULT_PORT, threads: DEFAULT_THREADS, tokens: None };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--root" => options.root = PathBuf::from(args.next().expect("Error: --root requires a folder")),
+            "--port" => options.port = args.next().and_then(|p| p.parse().ok()).expect("Error: --port requires a port number"),
+            "--tokens" => options.tokens = Some(PathBuf::from(args.next().expect("Error: --tokens requires a file"))),
+            "--threads" | "-t" => options.threads = args.next().and_then(|t| t.parse().ok()).filter(|t| *t > 0).expect("Error: --threads requires a positive number"),
+            // also tells the orchestrator that this build runs on the VM
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            },
+            _ => panic!("Error: unknown option '{}'. {}", arg, USAGE),
+        }
+    }
+    options
+}
+
+/// Serves the files of the root folder until the process is stopped
+pub fn run(args: &[String]) {
+    let options = parse_options(args);
+    let root = fs::canonicalize(&options.root).unwrap_or_else(|_| panic!("Error: cannot serve {}, the folder does not exist", options.root.display()));
+    let listener = TcpListener::bind(("0.0.0.0", options.port)).unwrap_or_else(|e| panic!("Error: could not listen on port {}: {}", options.port, e));
+    if let Some(tokens_fn) = &options.tokens {
+        read_tokens(tokens_fn).unwrap_or_else(|e| panic!("Error: could not read the tokens file {}: {}", tokens_fn.display(), e));
+    }
+    println_with_time!("Serve - Serving {} on port {} with {} threads{}", root.display(), options.port, options.threads,
+        if options.tokens.is_some() { ", machines need their token" } else { ", without authentication" });
+
+    let (sender, receiver) = mpsc::channel::<TcpStream>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..options.threads {
+        let (receiver, root, tokens) = (receiver.clone(), root.clone(), options.tokens.clone());
+        thread::spawn(move || loop {
+            let stream = match receiver.lock().unwrap().recv() {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let peer = stream.peer_addr().map(|a| a.ip().to_string()).unwrap_or_else(|_| "unknown".to_string());
+            if let Err(e) = handle(stream, &root, tokens.as_deref()) {
+                println_with_time!("Serve - Request from {} failed: {}", peer, e);
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => sender.send(stream).expect("Error: all the threads of the file server stopped"),
+            Err(e) => println_with_time!("Serve - Could not accept a connection: {}", e),
+        }
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(None),
+    };
+
+    let mut request = Request { method, path: target.split('?').next().unwrap_or("").to_string(), token: None, content_length: None, expect_continue: false, chunked: false };
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            return Ok(Some(request));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_lowercase().as_ref() {
+                "content-length" => request.content_length = value.parse().ok(),
+                "expect" => request.expect_continue = value.eq_ignore_ascii_case("100-continue"),
+                "transfer-encoding" => request.chunked = value.to_lowercase().contains("chunked"),
+                "authorization" => request.token = value.strip_prefix("Bearer ").map(|t| t.trim().to_string()),
+                _ => {},
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Decodes %XX escapes of the URL path
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            ret.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        }
+        else {
+            ret.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(ret).ok()
+}
+
+/// Path of the file under the root, or None if the URL path tries to get out of it
+fn local_path(root: &Path, url_path: &str) -> Option<(PathBuf, Vec<String>)> {
+    let decoded = percent_decode(url_path)?;
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    let mut components = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(c) => components.push(c.to_str()?.to_string()),
+            Component::CurDir => {},
+            _ => return None,
+        }
+    }
+    Some((components.iter().fold(root.to_path_buf(), |p, c| p.join(c)), components))
+}
+
+/// The tokens file that applies to the path, and the components of the path within the folder of its run
+fn scope<'a>(root: &Path, tokens_fn: Option<&Path>, components: &'a [String]) -> (Option<PathBuf>, &'a [String]) {
+    match components {
+        [runs, run_id, rest @ ..] if runs == crate::orchestrator::SHARED_RUNS_DIR => (Some(root.join(runs).join(run_id).join(crate::orchestrator::WEBSERVER_TOKENS_FN)), rest),
+        _ => (tokens_fn.map(|t| t.to_path_buf()), components),
+    }
+}
+
+/// Token -> machine name
+fn read_tokens(tokens_fn: &Path) -> io::Result<HashMap<String, String>> {
+    let mut ret = HashMap::new();
+    for line in fs::read_to_string(tokens_fn)?.lines() {
+        if let (Some(machine), Some(token)) = (line.split_whitespace().next(), line.split_whitespace().nth(1)) {
+            ret.insert(token.to_string(), machine.to_string());
+        }
+    }
+    Ok(ret)
+}
+
+/// True if the machine can access the path, given as its components under the root
+fn allowed(machine: &str, components: &[String]) -> bool {
+    let component = |idx: usize| components.get(idx).map(|c| c.as_str());
+    match component(0) {
+        Some("common_data") | Some("barriers") => true,
+        Some(UPLOAD_DIR) => component(1) == Some(machine),
+        Some("uploads") => component(2) == Some(machine),
+        Some(first) => first == machine,
+        None => false,
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}", status, body.len(), body)?;
+    stream.flush()
+}
+
+fn handle(stream: TcpStream, root: &Path, tokens_fn: Option<&Path>) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let request = match read_request(&mut reader)? {
+        Some(request) => request,
+        None => return respond(&mut writer, "400 Bad Request", "Bad request\n"),
+    };
+    let (path, components) = match local_path(root, &request.path) {
+        Some(found) => found,
+        None => return respond(&mut writer, "403 Forbidden", "Forbidden\n"),
+    };
+    let (tokens_fn, components) = scope(root, tokens_fn, &components);
+    if let Some(tokens_fn) = tokens_fn {
+        let machine = request.token.as_ref().and_then(|t| read_tokens(&tokens_fn).ok()?.remove(t));
+        match machine {
+            None => return respond(&mut writer, "401 Unauthorized", "A valid bearer token is required\n"),
+            Some(machine) if !allowed(&machine, components) => return respond(&mut writer, "403 Forbidden", "Forbidden\n"),
+            Some(_) => {},
+        }
+    }
+
+    match request.method.as_ref() {
+        "GET" | "HEAD" => {
+            let mut file = match fs::File::open(&path) {
+                Ok(file) if path.is_file() => file,
+                _ => return respond(&mut writer, "404 Not Found", "Not found\n"),
+            };
+            let length = file.metadata()?.len();
+            write!(writer, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: application/octet-stream\r\nConnection: close\r\n\r\n", length)?;
+            if request.method == "GET" {
+                io::copy(&mut file, &mut writer)?;
+            }
+            writer.flush()
+        },
+        "PUT" => {
+            // only [runs/<run id>/]machine_reports/<machine>/<file>, into the folder created for the machine during the setup
+            let machine_dir = path.parent().filter(|_| components.len() == 3 && components[0] == UPLOAD_DIR);
+            if !machine_dir.map(|d| d.is_dir()).unwrap_or(false) {
+                return respond(&mut writer, "403 Forbidden", "Uploads are only allowed into machine_reports/<machine>/\n");
+            }
+            let length = match (request.content_length, request.chunked) {
+                (Some(length), false) => length,
+                _ => return respond(&mut writer, "411 Length Required", "Content-Length required\n"),
+            };
+            if request.expect_continue {
+                writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+                writer.flush()?;
+            }
+
+            // written next to the target then renamed, so that readers never see a partial file
+            let partial = path.with_file_name(format!(".{}.partial", components[2]));
+            let mut file = fs::File::create(&partial)?;
+            let written = io::copy(&mut reader.by_ref().take(length), &mut file)?;
+            if written != length {
+                fs::remove_file(&partial).ok();
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("upload of {} ended after {} of {} bytes", request.path, written, length)));
+            }
+            fs::rename(&partial, &path)?;
+            respond(&mut writer, "201 Created", "")
+        },
+        _ => respond(&mut writer, "405 Method Not Allowed", "Method not allowed\n"),
+    }
+}
@@ -0,0 +1,306 @@
+// state/mod.rs
+//
+// Keeps track of how far a deployment went, so that a run that failed half-way
+// can be picked up again with `orchestrator resume` instead of starting from scratch.
+// The state is written to disk every time a phase is completed.
+//
+// Every run has its own ID, which namespaces its cloud resources and its local
+// files (all stored under runs/<run id>/).
+
+use serde::{Serialize, Deserialize};
+use std::sync::Mutex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use crate::paths;
+use crate::teardown::TeardownPolicy;
+use crate::pipelines::distribution::Distribution;
+use crate::orchestrator::Shared;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+
+const RUN_ID_LEN: usize = 8;
+const PASSWORD_LEN: usize = 24;
+const RUN_ID_MAX_LEN: usize = 16;
+const WEBSERVER_TOKEN_LEN: usize = 32;
+
+/// Options given on the command line when starting a run
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RunOptions {
+    /// Use this run ID instead of a randomly generated one
+    pub run_id: Option<String>,
+    /// Number of times the RUN steps without their own `repeat` are executed
+    pub repeat: Option<usize>,
+    /// Run whose report this run is compared with once its steps are over
+    #[serde(default)]
+    pub baseline: Option<String>,
+    /// Regression threshold of the comparison with the baseline, in percent
+    #[serde(default)]
+    pub threshold_percent: Option<f64>,
+    /// Seed of the random choice of the machine behind ¥{=>role}, round-robin without one
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Unknown replacement tokens are errors instead of warnings, and the scenario is linted before deploying anything
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// Every checkpoint of a deployment that can be skipped when resuming
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Phase {
+    /// The network (vnet and subnets) of the given system has been created
+    Network(String),
+    /// The VM with the given name has been created
+    Vm(String),
+    Orchestrator,
+    /// The network of the run has been peered with the one of the shared orchestrator
+    Peered,
+    WebserverPrepared,
+    DataPushed,
+    /// The pipeline step with the given index has finished on the given machine
+    Step(usize, String),
+    /// The readiness checks of the pipeline step with the given index have passed
+    Ready(usize),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DeploymentState {
+    pub scenario: String,
+    /// Unique identifier of the run, used to tag all cloud resources
+    pub run_id: String,
+    pub teardown: Option<TeardownPolicy>,
+    /// RFC 3339 date after which the resources of this run can be reaped
+    pub expires_at: Option<String>,
+    pub options: RunOptions,
+    pub completed: Vec<Phase>,
+    /// Machine name -> bearer token the machine uses to talk to the orchestrator webserver
+    #[serde(default)]
+    pub webserver_tokens: BTreeMap<String, String>,
+    /// How the files reach the machines, chosen when the run starts
+    #[serde(default)]
+    pub distribution: Option<Distribution>,
+    /// The long-lived orchestrator the run uses instead of creating its own, if any
+    #[serde(default)]
+    pub shared_orchestrator: Option<Shared>,
+    /// Machine name -> role -> machine picked for ¥{=>role} in the templates of the machine
+    #[serde(default)]
+    pub role_peers: BTreeMap<String, BTreeMap<String, String>>,
+    /// Machine name -> password generated for the run, for the machines without one in system.yml and the orchestrator
+    #[serde(default)]
+    pub passwords: BTreeMap<String, String>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<DeploymentState> = Mutex::new(DeploymentState::default());
+}
+
+/// Starts tracking a brand new deployment in its own run directory, and makes it the last run
+pub fn start(scenario: &str, options: RunOptions) {
+    let run_id = match &options.run_id {
+        Some(id) => check_run_id(id),
+        None => generate_run_id(),
+    };
+    if Path::new(&format!("{}/{}", paths::RUNS_PATH, run_id)).exists() {
+        panic!("Error: run {} already exists. Use 'orchestrator resume {}' to continue it, or 'orchestrator delete {}' to remove it first.", run_id, run_id, run_id);
+    }
+
+    let mut state = STATE.lock().unwrap();
+    state.scenario = scenario.to_string();
+    state.run_id = run_id;
+    state.teardown = None;
+    state.expires_at = None;
+    state.options = options;
+    state.completed.clear();
+    state.webserver_tokens.clear();
+    state.distribution = None;
+    state.shared_orchestrator = None;
+    state.role_peers.clear();
+    state.passwords.clear();
+    fs::create_dir_all(format!("{}/{}", paths::RUNS_PATH, state.run_id)).unwrap_or_else(|_| panic!("Error: could not create the run directory in {}. Please check permissions", paths::RUNS_PATH));
+    fs::write(paths::LAST_RUN_FN, &state.run_id).unwrap_or_else(|_| panic!("Error: could not write {}. Please check permissions", paths::LAST_RUN_FN));
+    save(&state);
+}
+
+/// Returns the ID of the most recently started run
+pub fn last_run_id() -> String {
+    let id = fs::read_to_string(paths::LAST_RUN_FN).unwrap_or_else(|_| panic!("Error: could not open {}. Make sure a deployment has been started first, as well as the file permissions.", paths::LAST_RUN_FN));
+    id.trim().to_string()
+}
+
+/// Loads the state of the given run (or the last one) from disk and returns the name of its scenario
+pub fn load(run_id: Option<&str>) -> String {
+    let run_id = check_run_id(&run_id.map(|id| id.to_string()).unwrap_or_else(last_run_id));
+    let state_fn = format!("{}/{}/{}", paths::RUNS_PATH, run_id, paths::DEPLOYMENT_STATE_FN);
+    let raw = fs::read_to_string(&state_fn).unwrap_or_else(|_| panic!("Error: could not open {}. Make sure run {} exists, as well as the file permissions.", state_fn, run_id));
+    let loaded: DeploymentState = serde_yaml::from_str(&raw).unwrap_or_else(|e| panic!("Error: {} is not a valid deployment state. Issue:\n{}\n", state_fn, e));
+    let scenario = loaded.scenario.clone();
+    *STATE.lock().unwrap() = loaded;
+    scenario
+}
+
+/// Azure names are case-insensitive, so only lowercase characters are used
+fn generate_run_id() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(RUN_ID_LEN).collect::<String>().to_lowercase()
+}
+
+/// Letters and digits only, so that the password can be given to the shell as is, with at least a lowercase letter,
+/// an uppercase letter and a digit as Azure requires
+fn generate_password() -> String {
+    loop {
+        let password: String = rand::thread_rng().sample_iter(&Alphanumeric).take(PASSWORD_LEN).collect();
+        if password.chars().any(|c| c.is_ascii_lowercase()) && password.chars().any(|c| c.is_ascii_uppercase()) && password.chars().any(|c| c.is_ascii_digit()) {
+            return password;
+        }
+    }
+}
+
+/// Run IDs end up in every resource name, so they must stay short and only use characters that Azure accepts everywhere
+pub fn check_run_id(run_id: &str) -> String {
+    let valid = !run_id.is_empty() && run_id.len() <= RUN_ID_MAX_LEN && run_id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') && !run_id.starts_with('-') && !run_id.ends_with('-');
+    if !valid {
+        panic!("Error: invalid run ID '{}'. It must be at most {} characters long and only contain lowercase letters, digits and '-'.", run_id, RUN_ID_MAX_LEN);
+    }
+    run_id.to_string()
+}
+
+pub fn run_id() -> String {
+    STATE.lock().unwrap().run_id.clone()
+}
+
+/// Path of a file or folder inside the directory of the current run
+pub fn run_path(name: &str) -> String {
+    format!("{}/{}/{}", paths::RUNS_PATH, run_id(), name)
+}
+
+pub fn options() -> RunOptions {
+    STATE.lock().unwrap().options.clone()
+}
+
+pub fn teardown_policy() -> Option<TeardownPolicy> {
+    STATE.lock().unwrap().teardown.clone()
+}
+
+pub fn expires_at() -> Option<String> {
+    STATE.lock().unwrap().expires_at.clone()
+}
+
+/// Records the teardown policy of the run, along with the resulting expiry date.
+/// A resumed run keeps the policy and expiry that were chosen when it was first started.
+pub fn set_teardown_if_unset(policy: TeardownPolicy) {
+    let mut state = STATE.lock().unwrap();
+    if state.teardown.is_none() {
+        state.expires_at = Some(policy.expiry().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+        state.teardown = Some(policy);
+        save(&state);
+    }
+}
+
+/// Returns the webserver token of every machine, generating the missing ones.
+/// Tokens are kept in the state so that a resumed run can still reach the webserver.
+pub fn webserver_tokens<'a, I>(machine_names: I) -> BTreeMap<String, String>
+    where I: IntoIterator<Item = &'a String> {
+    let mut state = STATE.lock().unwrap();
+    for name in machine_names {
+        if !state.webserver_tokens.contains_key(name) {
+            let token = rand::thread_rng().sample_iter(&Alphanumeric).take(WEBSERVER_TOKEN_LEN).collect::<String>();
+            state.webserver_tokens.insert(name.clone(), token);
+        }
+    }
+    save(&state);
+    state.webserver_tokens.clone()
+}
+
+pub fn webserver_token(machine: &str) -> String {
+    STATE.lock().unwrap().webserver_tokens.get(machine).cloned().unwrap_or_else(|| panic!("Error: machine '{}' has no webserver token, the webserver was not prepared for it", machine))
+}
+
+/// Records the distribution mode of the run, unless one was already chosen when it was first started, and returns the one in use
+pub fn set_distribution_if_unset(distribution: Distribution) -> Distribution {
+    let mut state = STATE.lock().unwrap();
+    if state.distribution.is_none() {
+        state.distribution = Some(distribution);
+        save(&state);
+    }
+    state.distribution.unwrap()
+}
+
+/// The distribution mode of the run, the webserver unless chosen otherwise
+pub fn distribution() -> Distribution {
+    STATE.lock().unwrap().distribution.unwrap_or(Distribution::Webserver)
+}
+
+/// Records which shared orchestrator the run uses, None if it creates its own
+pub fn set_shared_orchestrator(shared: Option<Shared>) {
+    let mut state = STATE.lock().unwrap();
+    state.shared_orchestrator = shared;
+    save(&state);
+}
+
+pub fn shared_orchestrator() -> Option<Shared> {
+    STATE.lock().unwrap().shared_orchestrator.clone()
+}
+
+pub fn role_peers() -> BTreeMap<String, BTreeMap<String, String>> {
+    STATE.lock().unwrap().role_peers.clone()
+}
+
+/// Records the machine behind ¥{=>role} for every machine and role
+pub fn set_role_peers(role_peers: BTreeMap<String, BTreeMap<String, String>>) {
+    let mut state = STATE.lock().unwrap();
+    state.role_peers = role_peers;
+    save(&state);
+}
+
+/// Password of the given machine for this run, generated the first time it is asked for
+pub fn password(machine_name: &str) -> String {
+    let mut state = STATE.lock().unwrap();
+    if let Some(password) = state.passwords.get(machine_name) {
+        return password.clone();
+    }
+    let password = generate_password();
+    state.passwords.insert(machine_name.to_string(), password.clone());
+    save(&state);
+    password
+}
+
+pub fn is_done(phase: &Phase) -> bool {
+    STATE.lock().unwrap().completed.contains(phase)
+}
+
+/// Records a phase as completed and immediately writes the state to disk
+pub fn mark_done(phase: Phase) {
+    let mut state = STATE.lock().unwrap();
+    if !state.completed.contains(&phase) {
+        state.completed.push(phase);
+    }
+    save(&state);
+}
+
+fn save(state: &DeploymentState) {
+    // nothing to keep outside of a run, e.g. when linting a scenario
+    if state.run_id.is_empty() {
+        return;
+    }
+    let yml = serde_yaml::to_string(state).expect("Error: could not serialise the deployment state");
+    let state_fn = format!("{}/{}/{}", paths::RUNS_PATH, state.run_id, paths::DEPLOYMENT_STATE_FN);
+    // it holds the generated passwords
+    crate::utils::files::write_private(&state_fn, &yml).unwrap_or_else(|_| panic!("Error: could not write {}. Please check permissions", state_fn));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_lowercase_ids() {
+        assert_eq!(check_run_id("ci-42"), "ci-42");
+        assert_eq!(check_run_id(&generate_run_id()).len(), RUN_ID_LEN);
+    }
+
+    #[test]
+    fn rejects_ids_that_are_not_names() {
+        for id in &["", "../config", "a;rm -rf x", "Upper", "-lead", "trail-", "a/b", "much-too-long-run-id"] {
+            assert!(std::panic::catch_unwind(|| check_run_id(id)).is_err(), "'{}' should be rejected", id);
+        }
+    }
+}
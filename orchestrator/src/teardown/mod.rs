@@ -0,0 +1,97 @@
+// teardown/mod.rs
+//
+// Decides what happens to the cloud resources of a run once it is over.
+// Every resource is tagged with the run ID and an expiry date, so that even
+// resources that are kept around can later be removed with `orchestrator reap`.
+
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Duration, Utc};
+use crate::yamlsir;
+use crate::utils::global_config::DEFAULT_VALUES;
+use crate::cloud_functions;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TeardownPolicy {
+    /// Delete the resources at the end of the run, whatever the outcome
+    Always { ttl_hours: u64 },
+    /// Delete the resources only if every step (and the post-deployment script) succeeded
+    OnSuccess { ttl_hours: u64 },
+    /// Keep the resources, they can be reaped once the TTL has expired
+    Ttl { ttl_hours: u64 },
+}
+
+impl TeardownPolicy {
+
+    /// Builds the policy from the (optional) `teardown` section of system.yml, falling back to the default values
+    pub fn from_yamlsir(teardown: &Option<yamlsir::Teardown>) -> Self {
+        let (policy, ttl_hours) = match teardown {
+            Some(t) => (t.policy.clone().unwrap_or_else(|| DEFAULT_VALUES.teardown_policy.clone()), t.ttl_hours.unwrap_or(DEFAULT_VALUES.teardown_ttl_hours)),
+            None => (DEFAULT_VALUES.teardown_policy.clone(), DEFAULT_VALUES.teardown_ttl_hours),
+        };
+        match policy.to_lowercase().as_ref() {
+            "always" => TeardownPolicy::Always { ttl_hours },
+            "on_success" | "on-success" | "success" => TeardownPolicy::OnSuccess { ttl_hours },
+            "ttl" | "keep" => TeardownPolicy::Ttl { ttl_hours },
+            other => panic!("Error: unknown teardown policy '{}', please use one of: always, on_success, ttl", other),
+        }
+    }
+
+    fn ttl_hours(&self) -> u64 {
+        match self {
+            TeardownPolicy::Always { ttl_hours } | TeardownPolicy::OnSuccess { ttl_hours } | TeardownPolicy::Ttl { ttl_hours } => *ttl_hours,
+        }
+    }
+
+    /// Date after which the resources are considered expired. Resources of runs that delete
+    /// themselves still get one, in case the orchestrator is interrupted before the teardown.
+    pub fn expiry(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::hours(self.ttl_hours() as i64)
+    }
+
+    pub fn should_delete(&self, success: bool) -> bool {
+        match self {
+            TeardownPolicy::Always { .. } => true,
+            TeardownPolicy::OnSuccess { .. } => success,
+            TeardownPolicy::Ttl { .. } => false,
+        }
+    }
+}
+
+/// Deletes the cloud resources of a run, and what it left on the shared orchestrator if there is one
+pub fn delete_run(run_id: &str) {
+    cloud_functions::azure::delete_run_resources(run_id);
+    crate::orchestrator::release_shared(run_id);
+}
+
+/// Applies the teardown policy of the current run (if it got far enough to have one)
+pub fn apply(success: bool) {
+    let policy = match crate::state::teardown_policy() {
+        Some(p) => p,
+        None => return,
+    };
+    let run_id = crate::state::run_id();
+
+    if policy.should_delete(success) {
+        println_with_time!("Teardown - Deleting all resources of run {}...", run_id);
+        delete_run(&run_id);
+        println_with_time!("Teardown - Finished deleting resources of run {}", run_id);
+    }
+    else {
+        println_with_time!("Teardown - Keeping resources of run {} until {} (remove them earlier with 'orchestrator delete', or later with 'orchestrator reap')", run_id, crate::state::expires_at().unwrap_or_default());
+    }
+}
+
+/// Deletes the resources of every TaaS run whose expiry date has passed
+pub fn reap() {
+    let now = Utc::now();
+    let expired_runs = cloud_functions::azure::expired_run_ids(&now);
+    if expired_runs.is_empty() {
+        println_with_time!("Reap - No expired TaaS resources found");
+        return;
+    }
+    for run_id in expired_runs {
+        println_with_time!("Reap - Deleting expired resources of run {}...", run_id);
+        delete_run(&run_id);
+    }
+    println_with_time!("Reap - Finished");
+}
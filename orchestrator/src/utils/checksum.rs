@@ -0,0 +1,34 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use sha2::{Digest, Sha256};
+
+/// Name of the checksum manifests, in the format of sha256sum
+pub const MANIFEST_FN: &str = "SHA256SUMS";
+
+/// Hex-encoded SHA-256 of a file
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> String {
+    let path = path.as_ref();
+    let mut file = fs::File::open(path).unwrap_or_else(|_| panic!("Error: could not open {} to compute its checksum. Please check permissions", path.display()));
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).unwrap_or_else(|_| panic!("Error: could not read {} to compute its checksum", path.display()));
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Manifest line of a file, as expected by `sha256sum -c`
+pub fn manifest_line<P: AsRef<Path>>(path: P, name: &str) -> String {
+    format!("{}  {}\n", sha256_file(path), name)
+}
+
+/// Writes the manifest of all the files of a folder (not recursive) into that folder
+pub fn write_manifest(dir: &str) {
+    let mut entries: Vec<_> = fs::read_dir(dir).unwrap_or_else(|_| panic!("Error: could not list {}. Please check permissions", dir))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file() && e.file_name() != MANIFEST_FN)
+        .collect();
+    entries.sort_by_key(|e| e.file_name());
+
+    let manifest: String = entries.iter().map(|e| manifest_line(e.path(), &e.file_name().to_string_lossy())).collect();
+    let manifest_fn = format!("{}/{}", dir, MANIFEST_FN);
+    fs::write(&manifest_fn, manifest).unwrap_or_else(|_| panic!("Error: could not write {}. Please check permissions", manifest_fn));
+}
@@ -9,4 +9,39 @@ pub fn list_all_files_with_ext<P: AsRef<Path>>(path: P, ext: &str) -> Vec<PathBu
     //TODO: handle all unwrap properly (with e.g. match), make less ugly
     let all_relevant = fs::read_dir(&path).unwrap().filter(|e| e.is_ok() && e.as_ref().unwrap().path().extension().map(|s| s.to_str().unwrap_or("err")).unwrap_or("err") == ext);
     all_relevant.map(|e| e.unwrap().path()).collect()
-}
\ No newline at end of file
+}
+
+/// Writes a file that only the current user can read, for the files holding credentials
+pub fn write_private<P: AsRef<Path>>(path: P, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // the mode above only applies to new files
+        if path.as_ref().exists() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(&path)?.write_all(content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_are_only_readable_by_their_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("taas_private_{}", std::process::id()));
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, "secret").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "secret");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -121,6 +121,54 @@ pub struct DefaultValues {
     pub remote_access: bool,
     pub config_template: String,
     pub custom_script: String,
+
+    #[serde(default = "use_default_teardown_policy")]
+    pub teardown_policy: String,
+    #[serde(default = "use_default_teardown_ttl_hours")]
+    pub teardown_ttl_hours: u64,
+
+    #[serde(default = "use_default_readiness_timeout_secs")]
+    pub readiness_timeout_secs: u64,
+
+    #[serde(default = "use_default_regression_threshold_percent")]
+    pub regression_threshold_percent: f64,
+
+    #[serde(default = "use_default_distribution")]
+    pub distribution: String,
+
+    #[serde(default = "use_default_direct_max_machines")]
+    pub direct_max_machines: usize,
+
+    #[serde(default = "use_default_username")]
+    pub username: String,
+}
+
+fn use_default_teardown_policy() -> String {
+    "ttl".to_string()
+}
+
+fn use_default_teardown_ttl_hours() -> u64 {
+    72
+}
+
+fn use_default_readiness_timeout_secs() -> u64 {
+    300
+}
+
+fn use_default_regression_threshold_percent() -> f64 {
+    10.0
+}
+
+fn use_default_distribution() -> String {
+    "auto".to_string()
+}
+
+fn use_default_direct_max_machines() -> usize {
+    3
+}
+
+fn use_default_username() -> String {
+    "taasuser".to_string()
 }
 
 #[inline]
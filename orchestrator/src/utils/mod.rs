@@ -6,4 +6,6 @@ pub mod math;
 pub mod replace;
 pub mod roles;
 pub mod files;
-pub mod run_parser;
\ No newline at end of file
+pub mod run_parser;
+pub mod run_include;
+pub mod checksum;pub mod ssh_key;
@@ -1,24 +1,82 @@
+// utils/replace.rs
+//
+// The template language of the deployment templates, step scripts and post-deployment script:
+//   ¥{key}                          value of the key in the replacement map
+//   ¥{key:-fallback}                the fallback if the key is missing or empty
+//   ¥{key|upper}                    the value through filters: upper, lower, base64, json-escape, shell-quote
+//   ¥{list[*:separator]}            a list token (e.g. ¥{=>server[*]}) joined with another separator
+//   ¥{if role=db}...¥{else}...¥{end}   a block kept only if the machine has the role, the key has the value (key=value,
+//                                   key!=value) or the key is set and not empty (key, !key)
+//   ¥{for ip in =>server}...¥{end}  the block once per item of a list, with ¥{ip} (and ¥{ip/<attribute>} for
+//                                   the peers of a role) bound to the item
+// Unknown keys are left in place, with a warning or an error depending on the fail policy. ¥¥{ is written as a literal ¥{.
+
 use std::fs;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 const SPECIAL_CHAR: char = '¥';
-const USUAL_IDENT_SIZE: usize = 20; //the expected length of a special identifier (inside ¥{  })
+const OPENING: &str = "¥{";
+const ESCAPED_OPENING: &str = "¥¥{";
+const FILTERS: [&str; 5] = ["upper", "lower", "base64", "json-escape", "shell-quote"];
+/// Separator of the list tokens (e.g. ¥{=>server[*]}), which can be joined with another one with ¥{=>server[*:<separator>]}
+pub const LIST_SEPARATOR: &str = ",";
 
+#[derive(Clone, Copy)]
 pub enum ReplaceFailPolicy {
-    #[allow(unused)]
     Ignore,
     Warn,
-    #[allow(unused)]
     Panic,
 }
 
 /// Takes an existing file name, opens it, replace special identifiers (using the 'replace' function below),
-/// then write a new file with the replaced content
+/// then write a new file with the replaced content and the same permissions. Binary files are copied verbatim.
 pub fn copy_and_replace_v2(orig_fn: &str, target_fn: &str, replacement: &HashMap<String, String>, fail_policy: ReplaceFailPolicy) {
-    let orig: String = fs::read_to_string(orig_fn).unwrap_or_else(|_| panic!("Could not find file {}", orig_fn));
+    let orig = fs::read(orig_fn).unwrap_or_else(|_| panic!("Could not find file {}", orig_fn));
+    let orig = match text_content(orig) {
+        Ok(text) => text,
+        Err(bytes) => {
+            println!("{} is not a text file, copying it without replacement.", orig_fn);
+            fs::write(target_fn, bytes).unwrap_or_else(|_| panic!("Could not create replaced file {}. Check permissions and whether the parent directory exists.", target_fn));
+            copy_permissions(orig_fn, target_fn);
+            return;
+        },
+    };
     let replaced = replace(&orig, replacement, fail_policy, Some(orig_fn.to_string()));
     fs::write(target_fn, replaced).expect(&format!("Could not create replaced file {}. Check permissions and whether the parent directory exists.", target_fn));
+    copy_permissions(orig_fn, target_fn);
+}
+
+/// Replaces every file of a directory and its subdirectories (see copy_and_replace_v2) into the same tree under the target directory
+pub fn copy_and_replace_dir(orig_dir: &str, target_dir: &str, replacement: &HashMap<String, String>, fail_policy: ReplaceFailPolicy) {
+    for orig in files_in(Path::new(orig_dir)) {
+        let target = Path::new(target_dir).join(orig.strip_prefix(orig_dir).unwrap());
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|_| panic!("Could not create directory {}. Check permissions.", parent.display()));
+        }
+        copy_and_replace_v2(&orig.to_string_lossy(), &target.to_string_lossy(), replacement, fail_policy);
+    }
+}
+
+/// Files of a directory and its subdirectories, in a fixed order. Empty if the directory does not exist.
+pub fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir).into_iter().flatten().flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+    for path in entries {
+        match path.is_dir() {
+            true => ret.extend(files_in(&path)),
+            false => ret.push(path),
+        }
+    }
+    ret
+}
+
+/// Gives the target file the permissions (e.g. executable) of the original one
+fn copy_permissions(orig_fn: &str, target_fn: &str) {
+    if let Ok(metadata) = fs::metadata(orig_fn) {
+        fs::set_permissions(target_fn, metadata.permissions()).unwrap_or_else(|_| panic!("Could not set the permissions of {}. Check permissions.", target_fn));
+    }
 }
 
 pub fn replace_and_write(orig_txt: &str, target_fn: &str, replacement: &HashMap<String, String>, fail_policy: ReplaceFailPolicy, associated_filename: Option<String>) {
@@ -26,99 +84,412 @@ pub fn replace_and_write(orig_txt: &str, target_fn: &str, replacement: &HashMap<
     fs::write(target_fn, replaced).expect(&format!("Could not create replaced file {}. Check permissions and whether the parent directory exists.", target_fn));
 }
 
-/// Runs through a given text and looks for ¥ symbols. When one is found, checks if the next
-/// character is '{'. If that is the case, collects all characters until a '}' is found. Once all
-/// characters are collected, tries to replace the collected characters with the replacement map,
-/// removing the whole '¥{...}'. If any of these checks fail, the function will silently continue
-/// looking for matches. If multiple '¥{' are found, the function will search for a new match, and
-/// once that is done, it will go back the the previous '¥' and try matching again.
-pub fn replace(text: &str, replacement: &HashMap<String, String>, fail_policy: ReplaceFailPolicy, associated_filename: Option<String>) -> String {
+/// The content of a file as text, or its bytes back if it looks binary (not UTF-8, or containing a NUL byte)
+fn text_content(bytes: Vec<u8>) -> Result<String, Vec<u8>> {
+    if bytes.contains(&0) {
+        return Err(bytes);
+    }
+    String::from_utf8(bytes).map_err(|e| e.into_bytes())
+}
+
+/// Value of a list token joined with a custom separator (¥{<list>[*:<separator>]}), in which \n and \t stand for a new line and a tab
+fn expand_list(ident: &str, ctx: &Context) -> Option<String> {
+    let (list, separator) = ident.strip_suffix(']')?.split_once("[*:")?;
+    let items = ctx.get(&format!("{}[*]", list))?;
+    let separator = separator.replace("\\n", "\n").replace("\\t", "\t");
+    Some(items.split(LIST_SEPARATOR).collect::<Vec<&str>>().join(&separator))
+}
+
+/// A parsed template
+enum Node {
+    Text(String),
+    Token(String),
+    If { condition: String, then: Vec<Node>, otherwise: Vec<Node> },
+    For { var: String, list: String, body: Vec<Node> },
+}
+
+/// What ends a sequence of nodes
+#[derive(PartialEq)]
+enum Closer {
+    Else,
+    End,
+    Eof,
+}
+
+/// The replacement map, along with the variables of the loops being rendered
+struct Context<'a> {
+    map: &'a HashMap<String, String>,
+    locals: Vec<(String, String)>,
+}
 
-    fn focus_replace(text: &str, _start: usize, map: &HashMap<String, String>, fail_policy: ReplaceFailPolicy, associated_filename: &Option<String>) -> String {
-
-        let mut has_warned = false;
-        let mut warned_tokens: HashSet<String> = HashSet::new();
-        let mut state: usize = 0;
-        let mut ident = String::with_capacity(USUAL_IDENT_SIZE);
-        let mut ret = String::with_capacity(USUAL_IDENT_SIZE);
-
-        for (idx, chr) in text.chars().enumerate() {
-
-            //check if we have reached the special symbol, and if there is still room in the string
-            //for '{}'
-            match state {
-                0 => {
-                     if chr == SPECIAL_CHAR && text.len() > idx+2 {
-                         state = 1;//ParserState::cheking_bracket;
-                     }
-                    else {
-                        ret.push(chr);
-                    }
+impl<'a> Context<'a> {
+    fn get(&self, key: &str) -> Option<String> {
+        self.locals.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v.clone()).or_else(|| self.map.get(key).cloned())
+    }
+}
+
+/// Reports the tokens without replacement according to the fail policy, only printing the whole text once
+struct Failures<'a> {
+    text: &'a str,
+    fail_policy: ReplaceFailPolicy,
+    associated_filename: &'a Option<String>,
+    /// Tokens without replacement, in the order they were found
+    missing: Vec<String>,
+}
+
+impl<'a> Failures<'a> {
+    fn not_found(&mut self, ident: &str) {
+        match self.fail_policy {
+            ReplaceFailPolicy::Panic => {
+                match self.associated_filename {
+                    Some(filename) => panic!("Error: in text file {}, found replacement token \"{}\", but could not find a suitable replacement.", filename, ident),
+                    None => panic!("\nError: found replacement token \"{}\", but could not find a suitable replacement. Text file:\n{}\n", ident, self.text),
+                };
+            },
+            ReplaceFailPolicy::Warn => {
+                if self.missing.is_empty() {
+                    match self.associated_filename {
+                        Some(filename) => println!("Warning: in text file {}, found replacement token \"{}\", but could not find a suitable replacement.", filename, ident),
+                        None => println!("\nWarning: found replacement token \"{}\", but could not find a suitable replacement. Text file:\n{}\n", ident, self.text),
+                    };
+                }
+                else if !self.missing.iter().any(|m| m == ident) {
+                    println!("For the same text file, the token {} was also found to have no match.", ident);
+                }
+            },
+            ReplaceFailPolicy::Ignore => (),
+        }
+        if !self.missing.iter().any(|m| m == ident) {
+            self.missing.push(ident.to_string());
+        }
+    }
+
+    /// Mistakes in the blocks cannot be left in place like unknown tokens
+    fn syntax_error(&self, message: &str) -> ! {
+        match self.associated_filename {
+            Some(filename) => panic!("Error: in text file {}, {}", filename, message),
+            None => panic!("\nError: {}. Text file:\n{}\n", message, self.text),
+        }
+    }
+}
+
+/// Splits the text into text and ¥{...} tokens. '¥¥{' stands for a literal '¥{', and a '¥' not followed by '{' is kept as is,
+/// and so is a '¥{' that is never closed.
+fn lex(text: &str) -> Vec<Node> {
+    let mut ret = Vec::new();
+    let mut current = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(SPECIAL_CHAR) {
+        current.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(after) = rest.strip_prefix(ESCAPED_OPENING) {
+            current.push_str(OPENING);
+            rest = after;
+        }
+        else if let Some(after) = rest.strip_prefix(OPENING) {
+            match after.find('}') {
+                Some(end) => {
+                    ret.push(Node::Text(std::mem::take(&mut current)));
+                    ret.push(Node::Token(after[..end].to_string()));
+                    rest = &after[end + 1..];
                 },
+                None => break,
+            }
+        }
+        else {
+            current.push(SPECIAL_CHAR);
+            rest = &rest[SPECIAL_CHAR.len_utf8()..];
+        }
+    }
+    current.push_str(rest);
+    ret.push(Node::Text(current));
+    ret
+}
 
-                1 => {
-                    if chr == '{' {
-                        state = 2;//ParserState::collecting;
-                    }
-                    else {
-                        state = 0;//ParserState::waiting;
-                        ret.push(SPECIAL_CHAR);
-                        ret.push(chr);
-                        ident.clear();
-
-                    }
+/// Builds the blocks out of the tokens, until the ¥{else} or ¥{end} of the enclosing block
+fn parse<I: Iterator<Item = Node>>(pieces: &mut I, failures: &Failures) -> (Vec<Node>, Closer) {
+    let mut ret = Vec::new();
+    while let Some(piece) = pieces.next() {
+        let ident = match piece {
+            Node::Token(ident) => ident,
+            other => {
+                ret.push(other);
+                continue;
+            },
+        };
+        let trimmed = ident.trim();
+        if trimmed == "end" {
+            return (ret, Closer::End);
+        }
+        else if trimmed == "else" {
+            return (ret, Closer::Else);
+        }
+        else if let Some(condition) = trimmed.strip_prefix("if ") {
+            let (then, end) = parse(pieces, failures);
+            let otherwise = match end {
+                Closer::Else => match parse(pieces, failures) {
+                    (otherwise, Closer::End) => otherwise,
+                    _ => failures.syntax_error(&format!("¥{{if {}}}...¥{{else}} is not closed with exactly one ¥{{end}}", condition.trim())),
                 },
+                Closer::End => Vec::new(),
+                Closer::Eof => failures.syntax_error(&format!("¥{{if {}}} is never closed with ¥{{end}}", condition.trim())),
+            };
+            ret.push(Node::If { condition: condition.trim().to_string(), then, otherwise });
+        }
+        else if let Some(rest) = trimmed.strip_prefix("for ") {
+            let (var, list) = match rest.split_once(" in ") {
+                Some((var, list)) if !var.trim().is_empty() && !list.trim().is_empty() => (var.trim().to_string(), list.trim().to_string()),
+                _ => failures.syntax_error(&format!("¥{{{}}} should be ¥{{for <name> in <list>}}", trimmed)),
+            };
+            let body = match parse(pieces, failures) {
+                (body, Closer::End) => body,
+                _ => failures.syntax_error(&format!("¥{{{}}} is not closed with ¥{{end}}", trimmed)),
+            };
+            ret.push(Node::For { var, list, body });
+        }
+        else {
+            ret.push(Node::Token(ident));
+        }
+    }
+    (ret, Closer::Eof)
+}
+
+fn apply_filter(filter: &str, value: String) -> String {
+    match filter {
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        "base64" => base64::encode(&value),
+        "json-escape" => {
+            let quoted = serde_json::to_string(&value).unwrap_or_default();
+            quoted[1..quoted.len() - 1].to_string()
+        },
+        "shell-quote" => format!("'{}'", value.replace('\'', "'\\''")),
+        _ => value,
+    }
+}
 
-                2 => {
-                    if chr == '}' {
-
-                        let map_res = map.get(&ident);
-                        match map_res {
-
-                            Some(s) => ret.push_str(s),
-                            None => {
-                                match fail_policy  {
-                                    ReplaceFailPolicy::Panic => {
-                                        match associated_filename {
-                                                Some(filename) => panic!("Error: in text file {}, found replacement token \"{}\", but could not find a suitable replacement.", filename, ident),
-                                                None => panic!("\nError: found replacement token \"{}\", but could not find a suitable replacement. Text file:\n{}\n", ident, text),
-                                            };
-                                    }
-                                    ReplaceFailPolicy::Warn => { //Only print the whole text once, o/w just mention which token was not found
-                                        if !has_warned {
-                                            match associated_filename {
-                                                Some(filename) => println!("Warning: in text file {}, found replacement token \"{}\", but could not find a suitable replacement.", filename, ident),
-                                                None => println!("\nWarning: found replacement token \"{}\", but could not find a suitable replacement. Text file:\n{}\n", ident, text),
-                                            };
-                                            warned_tokens.insert(ident.clone());
-                                            has_warned = true;
-                                        }
-                                        else {
-                                            if !warned_tokens.contains(&ident) {
-                                                println!("For the same text file, the token {} was also found to have no match.", ident);
-                                                warned_tokens.insert(ident.clone());
-                                            }
-                                        }
-                                    },
-                                    ReplaceFailPolicy::Ignore => (),
-                                };
-                                ret.push_str(&format!("{}{}{}{}", SPECIAL_CHAR, "{", ident, "}"));
-                            }
-                        };
-
-                        // ret.push_str(map.get(&ident).unwrap_or(&format!("{}{}{}{}", SPECIAL_CHAR, "{", ident, "}")));
-                        ident.clear();
-                        state = 0;//ParserState::waiting;
-                    }
-                    else {
-                        ident.push(chr);
-                    }
+/// Value of a key on its own, or of a list joined with a custom separator
+fn lookup(ident: &str, ctx: &Context) -> Option<String> {
+    ctx.get(ident).or_else(|| expand_list(ident, ctx))
+}
+
+/// Value of the content of a ¥{...} token, with its fallback and filters
+fn resolve(ident: &str, ctx: &Context) -> Option<String> {
+    if let Some(value) = lookup(ident, ctx) {
+        return Some(value);
+    }
+    // filters are only taken from the end, so that '|' can still appear in e.g. a list separator
+    let mut expression = ident;
+    let mut filters = Vec::new();
+    while let Some((rest, filter)) = expression.rsplit_once('|') {
+        if !FILTERS.contains(&filter.trim()) {
+            break;
+        }
+        filters.push(filter.trim());
+        expression = rest;
+    }
+    let value = match lookup(expression, ctx) {
+        Some(value) => value,
+        None => {
+            let (key, fallback) = expression.split_once(":-")?;
+            lookup(key, ctx).filter(|v| !v.is_empty()).unwrap_or_else(|| fallback.to_string())
+        },
+    };
+    Some(filters.iter().rev().fold(value, |value, filter| apply_filter(filter, value)))
+}
+
+/// Position of the '=' of a condition, which is not part of '=>' or '<='
+fn operator_position(condition: &str) -> Option<usize> {
+    let bytes = condition.as_bytes();
+    (0..bytes.len()).find(|i| bytes[*i] == b'=' && bytes.get(i + 1) != Some(&b'>') && (*i == 0 || (bytes[i - 1] != b'<' && bytes[i - 1] != b'=')))
+}
+
+fn evaluate(condition: &str, ctx: &Context) -> bool {
+    let equals = |key: &str, value: &str| match key {
+        "role" => resolve("roles", ctx).map(|roles| roles.split(LIST_SEPARATOR).any(|r| r == value)).unwrap_or(false),
+        key => resolve(key, ctx).unwrap_or_default() == value,
+    };
+    match operator_position(condition) {
+        Some(pos) if pos > 0 && condition.as_bytes()[pos - 1] == b'!' => !equals(condition[..pos - 1].trim(), condition[pos + 1..].trim()),
+        Some(pos) => equals(condition[..pos].trim(), condition[pos + 1..].trim()),
+        None => match condition.strip_prefix('!') {
+            Some(key) => resolve(key.trim(), ctx).unwrap_or_default().is_empty(),
+            None => !resolve(condition, ctx).unwrap_or_default().is_empty(),
+        },
+    }
+}
+
+/// Items of a list, given as the name of a list token (e.g. =>server for ¥{=>server[*]}) or of a key holding a list
+fn list_items(list: &str, ctx: &Context) -> Option<Vec<String>> {
+    let joined = ctx.get(&format!("{}[*]", list)).or_else(|| resolve(list, ctx))?;
+    match joined.is_empty() {
+        true => Some(Vec::new()),
+        false => Some(joined.split(LIST_SEPARATOR).map(|item| item.to_string()).collect()),
+    }
+}
+
+fn render(nodes: &[Node], ctx: &mut Context, failures: &mut Failures, ret: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => ret.push_str(text),
+            Node::Token(ident) => match resolve(ident, ctx) {
+                Some(value) => ret.push_str(&value),
+                None => {
+                    failures.not_found(ident);
+                    ret.push_str(&format!("{}{}}}", OPENING, ident));
                 },
-                _=> panic!(),
-            }
+            },
+            Node::If { condition, then, otherwise } => match evaluate(condition, ctx) {
+                true => render(then, ctx, failures, ret),
+                false => render(otherwise, ctx, failures, ret),
+            },
+            Node::For { var, list, body } => {
+                let items = list_items(list, ctx).unwrap_or_else(|| {
+                    failures.not_found(list);
+                    Vec::new()
+                });
+                for (idx, item) in items.into_iter().enumerate() {
+                    // the attributes of the i-th peer of a role (¥{=>server[i]/port}) are bound as ¥{<var>/port}
+                    let prefix = format!("{}[{}]/", list, idx);
+                    let mut locals: Vec<(String, String)> = ctx.map.iter()
+                        .filter_map(|(k, v)| k.strip_prefix(&prefix).map(|attribute| (format!("{}/{}", var, attribute), v.clone())))
+                        .collect();
+                    locals.push((var.clone(), item));
+                    let depth = ctx.locals.len();
+                    ctx.locals.extend(locals);
+                    render(body, ctx, failures, ret);
+                    ctx.locals.truncate(depth);
+                }
+            },
         }
-        ret
     }
-    let ret = focus_replace(text, 0, &replacement, fail_policy, &associated_filename);
+}
+
+/// Renders the template language described at the top of this file. Anything that does not parse as a token
+/// ('¥' without '{', '¥{' without '}') is kept as is, and so are the tokens without replacement, once reported
+/// according to the fail policy. Blocks that are not properly closed are always an error.
+pub fn replace(text: &str, replacement: &HashMap<String, String>, fail_policy: ReplaceFailPolicy, associated_filename: Option<String>) -> String {
+    let mut failures = Failures { text, fail_policy, associated_filename: &associated_filename, missing: Vec::new() };
+    render_text(text, replacement, &mut failures)
+}
+
+/// The tokens of the text that have no replacement, without reporting them
+pub fn unresolved_tokens(text: &str, replacement: &HashMap<String, String>, associated_filename: Option<String>) -> Vec<String> {
+    let mut failures = Failures { text, fail_policy: ReplaceFailPolicy::Ignore, associated_filename: &associated_filename, missing: Vec::new() };
+    render_text(text, replacement, &mut failures);
+    failures.missing
+}
+
+/// Policy for the tokens of the scenario files during a run: an error with --strict, a warning otherwise
+pub fn run_fail_policy() -> ReplaceFailPolicy {
+    match crate::state::options().strict {
+        true => ReplaceFailPolicy::Panic,
+        false => ReplaceFailPolicy::Warn,
+    }
+}
+
+fn render_text(text: &str, replacement: &HashMap<String, String>, failures: &mut Failures) -> String {
+    let nodes = match parse(&mut lex(text).into_iter(), failures) {
+        (nodes, Closer::Eof) => nodes,
+        (_, Closer::Else) => failures.syntax_error("found ¥{else} outside of an ¥{if ...} block"),
+        (_, Closer::End) => failures.syntax_error("found ¥{end} without a block to close"),
+    };
+    let mut ctx = Context { map: replacement, locals: Vec::new() };
+    let mut ret = String::with_capacity(text.len());
+    render(&nodes, &mut ctx, failures, &mut ret);
     ret
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn render(text: &str, pairs: &[(&str, &str)]) -> String {
+        replace(text, &map(pairs), ReplaceFailPolicy::Ignore, None)
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(render("host=¥{=>db} me=¥{<= db}", &[("=>db", "10.0.0.4"), ("<= db", "10.0.0.5")]), "host=10.0.0.4 me=10.0.0.5");
+        assert_eq!(render("¥{unknown} stays", &[]), "¥{unknown} stays");
+    }
+
+    #[test]
+    fn fallbacks_and_filters() {
+        assert_eq!(render("¥{port:-5432}", &[]), "5432");
+        assert_eq!(render("¥{port:-5432}", &[("port", "")]), "5432");
+        assert_eq!(render("¥{port:-5432}", &[("port", "6543")]), "6543");
+        assert_eq!(render("¥{name|upper} ¥{name|base64}", &[("name", "db")]), "DB ZGI=");
+        assert_eq!(render("¥{missing:-none|upper}", &[]), "NONE");
+        assert_eq!(render("¥{text|json-escape}", &[("text", "a \"b\"\n")]), "a \\\"b\\\"\\n");
+        assert_eq!(render("¥{text|shell-quote}", &[("text", "it's")]), "'it'\\''s'");
+    }
+
+    #[test]
+    fn list_separators() {
+        let pairs = [("=>server[*]", "10.0.0.4,10.0.0.5")];
+        assert_eq!(render("¥{=>server[*]}", &pairs), "10.0.0.4,10.0.0.5");
+        assert_eq!(render("¥{=>server[*: ]}", &pairs), "10.0.0.4 10.0.0.5");
+        assert_eq!(render("¥{=>server[*:\\n]}", &pairs), "10.0.0.4\n10.0.0.5");
+    }
+
+    #[test]
+    fn conditions() {
+        let pairs = [("roles", "db,monitoring"), ("env", "prod"), ("empty", "")];
+        assert_eq!(render("¥{if role=db}db¥{else}other¥{end}", &pairs), "db");
+        assert_eq!(render("¥{if role=web}web¥{else}other¥{end}", &pairs), "other");
+        assert_eq!(render("¥{if env=prod}p¥{end}¥{if env!=prod}n¥{end}", &pairs), "p");
+        assert_eq!(render("¥{if empty}set¥{end}¥{if !empty}unset¥{end}", &pairs), "unset");
+        assert_eq!(render("¥{if =>db}peer¥{else}none¥{end}", &[("=>db", "10.0.0.4")]), "peer");
+    }
+
+    #[test]
+    fn loops() {
+        let pairs = [("=>server[*]", "10.0.0.4,10.0.0.5"), ("=>server[0]/port", "80"), ("=>server[1]/port", "8080")];
+        assert_eq!(render("¥{for s in =>server}¥{s}:¥{s/port};¥{end}", &pairs), "10.0.0.4:80;10.0.0.5:8080;");
+        assert_eq!(render("¥{for s in =>none}x¥{end}", &[("=>none[*]", "")]), "");
+        assert_eq!(render("¥{for a in =>server}¥{for b in =>server}.¥{end}¥{end}", &pairs), "....");
+    }
+
+    #[test]
+    #[should_panic(expected = "without a block to close")]
+    fn unbalanced_end() {
+        render("text¥{end}", &[]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unclosed_block() {
+        render("¥{if role=db}text", &[]);
+    }
+
+    #[test]
+    fn unresolved() {
+        let tokens = unresolved_tokens("¥{a} ¥{b:-x} ¥{c} ¥{a}", &map(&[("c", "1")]), None);
+        assert_eq!(tokens, vec!["a"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "could not find a suitable replacement")]
+    fn panic_policy() {
+        replace("¥{a}", &HashMap::new(), ReplaceFailPolicy::Panic, Some("f".to_string()));
+    }
+
+    #[test]
+    fn escapes_and_utf8() {
+        assert_eq!(render("¥¥{a} ¥{a}", &[("a", "1")]), "¥{a} 1");
+        assert_eq!(render("price: 5¥ ¥ {a} é ¥{a", &[("a", "1")]), "price: 5¥ ¥ {a} é ¥{a");
+        assert_eq!(render("¥{if a}¥¥{a}¥{end}", &[("a", "1")]), "¥{a}");
+    }
+
+    #[test]
+    fn binary_content() {
+        assert_eq!(text_content("¥{a} é".as_bytes().to_vec()), Ok("¥{a} é".to_string()));
+        assert_eq!(text_content(vec![b'a', 0, b'b']), Err(vec![b'a', 0, b'b']));
+        assert_eq!(text_content(vec![0xff, 0xfe]), Err(vec![0xff, 0xfe]));
+    }
+}
@@ -4,13 +4,33 @@ use lasir::machines::Vm;
 use lasir::connections::VmConnectionLogical;
 use lasir::machines::LogicalSystem;
 use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
+use crate::utils::replace::LIST_SEPARATOR;
 
+/// Spellings of the role tokens, e.g. ¥{=>server} and ¥{=> server}
+const PEER_PREFIXES: [&str; 3] = ["=>", "=> ", "=>  "];
+const SELF_PREFIXES: [&str; 3] = ["<=", "<= ", "<=  "];
+const COUNT_PREFIXES: [&str; 2] = ["#", "# "];
+/// Domain of the host names given to the machines in /etc/hosts, e.g. server-1.taas
+pub const HOSTS_DOMAIN: &str = "taas";
+/// Lines around the entries added to /etc/hosts, so that they can be replaced as a whole
+pub const HOSTS_BEGIN_MARKER: &str = "# BEGIN taas hosts";
+pub const HOSTS_END_MARKER: &str = "# END taas hosts";
+/// Longest label of a host name
+const HOST_LABEL_MAX_LEN: usize = 63;
+
+/// Replacements of each VM for the roles of the machines it is connected to:
+/// ¥{=>role} (one of them) and ¥{<=role} (the own IP address facing it),
+/// ¥{=>role[*]} and ¥{<=role[*]} (all of them, see replace::LIST_SEPARATOR), ¥{=>role[i]} and ¥{<=role[i]} (the i-th), and ¥{#role} (how many)
 pub fn create_ip_string_replacement_map(complete_map: Vec<HashMap<String, Vec<(Ipv4Addr, Ipv4Addr)>>>, singleton_map: Vec<HashMap<String, (Ipv4Addr, Ipv4Addr)>>) -> Vec<HashMap<String, String>> {
     let mut ret: Vec<HashMap<String, String>> = Vec::with_capacity(complete_map.len());
     for idx in 0..complete_map.len() {
-        // let complete = &complete_map[idx];
+        let complete = &complete_map[idx];
         let singleton = &singleton_map[idx];
         let mut new_complete_replacement: HashMap<String, String> = HashMap::new();
         let mut new_random_replacement: HashMap<String, String> = HashMap::new();
@@ -23,20 +43,92 @@ pub fn create_ip_string_replacement_map(complete_map: Vec<HashMap<String, Vec<(I
             new_random_replacement.insert(format!("<= {}", role), ipv4_self.to_string());
             new_random_replacement.insert(format!("<=  {}", role), ipv4_self.to_string());
         }
+        for (role, ipv4s) in complete {
+            let selves: Vec<String> = ipv4s.iter().map(|(ipv4_self, _)| ipv4_self.to_string()).collect();
+            let peers: Vec<String> = ipv4s.iter().map(|(_, ipv4_out)| ipv4_out.to_string()).collect();
+            for (prefixes, ips) in [(PEER_PREFIXES, &peers), (SELF_PREFIXES, &selves)] {
+                for prefix in prefixes {
+                    new_complete_replacement.insert(format!("{}{}[*]", prefix, role), ips.join(LIST_SEPARATOR));
+                    for (ip_idx, ip) in ips.iter().enumerate() {
+                        new_complete_replacement.insert(format!("{}{}[{}]", prefix, role, ip_idx), ip.clone());
+                    }
+                }
+            }
+            for prefix in COUNT_PREFIXES {
+                new_complete_replacement.insert(format!("{}{}", prefix, role), peers.len().to_string());
+            }
+        }
         new_complete_replacement.extend(new_random_replacement);
         ret.push(new_complete_replacement);
     }
     ret
 }
 
-//TODO: move in some util file
-fn pick_random(max: usize) -> usize {
-        assert!(max > 0);
+/// Replacements of each VM for the roles and their attributes: ¥{roles} (its own roles, see replace::LIST_SEPARATOR),
+/// ¥{roles/<role>/<attribute>} (its own attributes), and ¥{=>role/<attribute>} and ¥{=>role[i]/<attribute>}
+/// (those of the connected machines behind ¥{=>role} and ¥{=>role[i]}). Needs the peers picked by create_vm_local_ip_mapping().
+pub fn create_attribute_replacement_map<C: VmConnectionLogical>(lasir_system: &LogicalSystem<C>) -> Vec<HashMap<String, String>> {
+    let role_peers = crate::state::role_peers();
+    let all_roles = find_all_roles(&lasir_system.vms);
 
-        let mut rng = rand::thread_rng();
-        let rnd = Uniform::from(0..max);
-        let rnd = rnd.sample(&mut rng);
-        rnd
+    lasir_system.vms.iter().enumerate().map(|(idx, vm)| {
+        let mut ret: HashMap<String, String> = HashMap::new();
+        let names: Vec<&str> = vm.roles.iter().map(|r| r.name.as_str()).collect();
+        ret.insert("roles".to_string(), names.join(LIST_SEPARATOR));
+        ret.insert("roles[*]".to_string(), names.join(LIST_SEPARATOR));
+        for role in &vm.roles {
+            for (attribute, value) in &role.attributes {
+                ret.insert(format!("roles/{}/{}", role.name, attribute), value.clone());
+            }
+        }
+
+        for role in &all_roles {
+            let peers = find_all_connected_vms_with_role(idx, role, lasir_system);
+            let picked = role_peers.get(&vm.name).and_then(|p| p.get(role)).and_then(|name| lasir_system.vms.iter().find(|v| v.name == *name));
+            let indexed = peers.iter().enumerate().map(|(peer_idx, peer)| (format!("[{}]", peer_idx), &lasir_system.vms[*peer]));
+            for (suffix, peer) in picked.map(|p| (String::new(), p)).into_iter().chain(indexed) {
+                let attributes = match peer.role(role) {
+                    Some(r) => &r.attributes,
+                    None => continue,
+                };
+                for (attribute, value) in attributes {
+                    for prefix in PEER_PREFIXES {
+                        ret.insert(format!("{}{}{}/{}", prefix, role, suffix, attribute), value.clone());
+                    }
+                }
+            }
+        }
+        ret
+    }).collect()
+}
+
+/// How ¥{=>role} picks one of the connected machines with the role. Either way, the same scenario gets the same peers every time.
+enum PeerSelection {
+    /// The machines take the candidates in turn, in the order of system.yml, so that they spread evenly (role -> machines served so far)
+    RoundRobin(HashMap<String, usize>),
+    /// Picked at random, from the seed given with --seed
+    Seeded(Box<StdRng>),
+}
+
+impl PeerSelection {
+    fn new(seed: Option<u64>) -> PeerSelection {
+        match seed {
+            Some(seed) => PeerSelection::Seeded(Box::new(StdRng::seed_from_u64(seed))),
+            None => PeerSelection::RoundRobin(HashMap::new()),
+        }
+    }
+
+    fn pick(&mut self, role: &str, candidates: &[usize]) -> usize {
+        assert!(!candidates.is_empty());
+        match self {
+            PeerSelection::RoundRobin(served) => {
+                let count = served.entry(role.to_string()).or_insert(0);
+                *count += 1;
+                candidates[(*count - 1) % candidates.len()]
+            },
+            PeerSelection::Seeded(rng) => candidates[Uniform::from(0..candidates.len()).sample(rng.as_mut())],
+        }
+    }
 }
 
 pub fn create_vm_local_ip_mapping<'a, C: VmConnectionLogical>(lasir_system: &LogicalSystem<C>, _pasir_vms: &Vec<pasir::machines::Vm>, pasir_subnets: &Vec<pasir::connections::Subnet>) -> (Vec<HashMap<String, Vec<(Ipv4Addr, Ipv4Addr)>>>, Vec<HashMap<String, (Ipv4Addr, Ipv4Addr)>>) {
@@ -53,31 +145,113 @@ pub fn create_vm_local_ip_mapping<'a, C: VmConnectionLogical>(lasir_system: &Log
         vm_connected_roles_map.push(hm);
     }
 
+    // peers already picked when the run was started are kept, e.g. when it is resumed
+    let recorded = crate::state::role_peers();
+    let mut selection = PeerSelection::new(crate::state::options().seed);
+    let mut chosen: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+
     let hm_len = vm_connected_roles_map.len();
     let (mut complete_map, mut random_map): (Vec<HashMap<String, Vec<(Ipv4Addr, Ipv4Addr)>>>, Vec<HashMap<String, (Ipv4Addr, Ipv4Addr)>>) = (Vec::with_capacity(hm_len), Vec::with_capacity(hm_len));
     
     for (curr_vm, map) in vm_connected_roles_map.iter().enumerate() {
         let (mut new_complete_map, mut new_random_map): (HashMap<String, Vec<(Ipv4Addr, Ipv4Addr)>>, HashMap<String, (Ipv4Addr, Ipv4Addr)>) = (HashMap::new(), HashMap::new());
 
-        for (role, vms) in map {
+        // in a fixed order, for the selection to be the same every time
+        let mut roles: Vec<&String> = map.keys().collect();
+        roles.sort();
+        for role in roles {
+            let vms = &map[role];
             if vms.len() != 0 {
-                let random_singleton = vms[pick_random(vms.len())];
-                assert!(random_singleton != curr_vm, "A random VM chosen for IP mapping was the same as the source VM, index {}", random_singleton);
+                let vm_name = &lasir_system.vms[curr_vm].name;
+                let peer_vm = recorded.get(vm_name).and_then(|peers| peers.get(role))
+                    .and_then(|peer| vms.iter().find(|idx| lasir_system.vms[**idx].name == *peer).copied())
+                    .unwrap_or_else(|| selection.pick(role, vms));
+                chosen.entry(vm_name.clone()).or_default().insert(role.clone(), lasir_system.vms[peer_vm].name.clone());
+                assert!(peer_vm != curr_vm, "The VM picked for IP mapping was the same as the source VM, index {}", peer_vm);
 
                 let all_ipv4: Vec<(Ipv4Addr, Ipv4Addr)> = vms.iter().map(|vm| lasir_connection_to_ip(curr_vm, *vm, pasir_subnets)
                                                 .unwrap_or_else(|| panic!("Failed to find in pasir two VMs connected in lasir, indices {} and {}", curr_vm, *vm))).collect();
-                let random_ipv4: (Ipv4Addr, Ipv4Addr) = lasir_connection_to_ip(curr_vm, random_singleton, pasir_subnets)
-                                    .unwrap_or_else(|| panic!("Failed to find in pasir two VMs connected in lasir (one chosen randomly), indices {} and {}", curr_vm, random_singleton));
+                let random_ipv4: (Ipv4Addr, Ipv4Addr) = lasir_connection_to_ip(curr_vm, peer_vm, pasir_subnets)
+                                    .unwrap_or_else(|| panic!("Failed to find in pasir two VMs connected in lasir (one picked for a role), indices {} and {}", curr_vm, peer_vm));
                 new_complete_map.insert(role.clone(), all_ipv4);
                 new_random_map.insert(role.clone(), random_ipv4);
             }
+            else {
+                // still listed, so that the count of the role is 0
+                new_complete_map.insert(role.clone(), Vec::new());
+            }
         }
         complete_map.push(new_complete_map);
         random_map.push(new_random_map);
     }
+    crate::state::set_role_peers(chosen);
     (complete_map, random_map)
 }
 
+/// /etc/hosts entries of each VM, giving the IP address on their shared subnet to the machines it is connected to
+/// (<name>.taas and <name>) and to its roles: <role>.taas for the machine behind ¥{=>role}, and <i>.<role>.taas for ¥{=>role[i]}.
+/// Must be called after create_vm_local_ip_mapping, which picks the machine behind each role.
+pub fn create_hosts_fragments<C: VmConnectionLogical>(lasir_system: &LogicalSystem<C>, pasir_subnets: &Vec<pasir::connections::Subnet>) -> Vec<String> {
+    let all_roles = find_all_roles(&lasir_system.vms);
+    let role_peers = crate::state::role_peers();
+    let mut ret = Vec::with_capacity(lasir_system.vms.len());
+    // names already warned about, as they show up in the fragment of every machine
+    let mut warned = BTreeSet::new();
+    for (curr_vm, vm) in lasir_system.vms.iter().enumerate() {
+        let peer_ip = |peer: usize| lasir_connection_to_ip(curr_vm, peer, pasir_subnets).map(|(_, ip)| ip);
+        let mut fragment = format!("{}\n", HOSTS_BEGIN_MARKER);
+        for (peer, peer_vm) in lasir_system.vms.iter().enumerate().filter(|(peer, _)| *peer != curr_vm) {
+            if let (Some(ip), Some(label)) = (peer_ip(peer), checked_host_label(&peer_vm.name, "machine", &mut warned)) {
+                fragment.push_str(&format!("{} {}.{} {}\n", ip, label, HOSTS_DOMAIN, label));
+            }
+        }
+        for role in &all_roles {
+            let role_label = match checked_host_label(role, "role", &mut warned) {
+                Some(label) => label,
+                None => continue,
+            };
+            let peers = find_all_connected_vms_with_role(curr_vm, role, lasir_system);
+            let picked = role_peers.get(&vm.name).and_then(|picks| picks.get(role))
+                .and_then(|name| peers.iter().find(|idx| lasir_system.vms[**idx].name == *name));
+            if let Some(ip) = picked.and_then(|peer| peer_ip(*peer)) {
+                fragment.push_str(&format!("{} {}.{}\n", ip, role_label, HOSTS_DOMAIN));
+            }
+            for (idx, peer) in peers.iter().enumerate() {
+                if let Some(ip) = peer_ip(*peer) {
+                    fragment.push_str(&format!("{} {}.{}.{}\n", ip, idx, role_label, HOSTS_DOMAIN));
+                }
+            }
+        }
+        fragment.push_str(&format!("{}\n", HOSTS_END_MARKER));
+        ret.push(fragment);
+    }
+    ret
+}
+
+/// The name as a host name label: lowercase letters, digits and '-', any other character becoming '-'.
+/// None if nothing is left of it
+pub fn host_label(name: &str) -> Option<String> {
+    let label: String = name.to_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    let label: String = label.trim_matches('-').chars().take(HOST_LABEL_MAX_LEN).collect();
+    let label = label.trim_end_matches('-');
+    match label.is_empty() {
+        true => None,
+        false => Some(label.to_string()),
+    }
+}
+
+/// host_label(), warning once per name when it is not already a valid label
+fn checked_host_label(name: &str, what: &str, warned: &mut BTreeSet<String>) -> Option<String> {
+    let label = host_label(name);
+    if label.as_deref() != Some(name) && warned.insert(name.to_string()) {
+        match &label {
+            Some(label) => println!("Warning: {} '{}' is not a valid host name, it is written as '{}' in /etc/hosts", what, name, label),
+            None => println!("Warning: {} '{}' is not a valid host name and has no entry in /etc/hosts", what, name),
+        }
+    }
+    label
+}
+
 #[inline]
 // Peppered with many asserts to make sure everythong is fine
 fn lasir_connection_to_ip(vm_a: usize, vm_b: usize, pasir_subnets: &Vec<pasir::connections::Subnet>) -> Option<(Ipv4Addr, Ipv4Addr)> {
@@ -111,7 +285,7 @@ fn lasir_connection_to_ip(vm_a: usize, vm_b: usize, pasir_subnets: &Vec<pasir::c
 }
 
 #[inline]
-fn find_all_roles(vms: &Vec<Vm>) -> Vec<String> {
+fn find_all_roles(vms: &[Vm]) -> Vec<String> {
 
     // fn update<'a>(ret: &'a mut Vec<String>, stri: &String) -> &'a Vec<String> {
     //     if !ret.contains(&stri) {
@@ -122,9 +296,9 @@ fn find_all_roles(vms: &Vec<Vm>) -> Vec<String> {
 
     let mut ret = Vec::new();
 
-    for vm in vms {
-        if !ret.contains(&vm.role) {
-            ret.push(vm.role.to_string())
+    for role in vms.iter().flat_map(|vm| &vm.roles) {
+        if !ret.contains(&role.name) {
+            ret.push(role.name.to_string())
         }
     }
     ret
@@ -135,6 +309,24 @@ fn find_all_roles(vms: &Vec<Vm>) -> Vec<String> {
 fn find_all_connected_vms_with_role<'a, C: VmConnectionLogical>(source_vm: usize, role: &str, system: &'a LogicalSystem<C>) -> Vec<usize> {
 
     let all_connected_vms: Vec<usize> = system.network.all_connections_for_vm(source_vm);
-    let all_relevant_vm_idxs: Vec<usize> = all_connected_vms.iter().map(|idx| (idx, system.vms.get(*idx).unwrap())).filter(|(_, vm)| vm.role == role).map(|(idx, _)| *idx).collect();
+    let all_relevant_vm_idxs: Vec<usize> = all_connected_vms.iter().map(|idx| (idx, system.vms.get(*idx).unwrap())).filter(|(_, vm)| vm.has_role(role)).map(|(idx, _)| *idx).collect();
     all_relevant_vm_idxs
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_host_labels_are_kept() {
+        assert_eq!(host_label("client-1").as_deref(), Some("client-1"));
+        assert_eq!(host_label("db").as_deref(), Some("db"));
+    }
+
+    #[test]
+    fn invalid_host_labels_are_normalised() {
+        assert_eq!(host_label("Web_Server").as_deref(), Some("web-server"));
+        assert_eq!(host_label("_db.").as_deref(), Some("db"));
+        assert_eq!(host_label("__"), None);
+        assert_eq!(host_label(&"a".repeat(70)).map(|l| l.len()), Some(HOST_LABEL_MAX_LEN));
+    }
+}
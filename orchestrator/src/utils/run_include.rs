@@ -0,0 +1,236 @@
+// run_include.rs
+//
+// Composition of pipeline.run files, done on the text before it is parsed:
+//   ¥ INCLUDE <path>       replaced by the content of the file, resolved relative to the including
+//                          file, the scenario, then scenarios/common_steps/
+//   ¥ SNIPPET <name>       starts the definition of a named snippet, ended by '¥ END'
+//   ¥ USE <name>           replaced by the content of the snippet
+// Snippets can be defined in any file (included or not) and used before their definition.
+
+use std::fs;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::paths;
+
+const INCLUDE_MARKER: &str = r"^\s*¥ INCLUDE[ \t]+(\S+)\s*$";
+const SNIPPET_MARKER: &str = r"^\s*¥ SNIPPET[ \t]+(\S+)\s*$";
+const END_MARKER: &str = r"^\s*¥ END\s*$";
+const USE_MARKER: &str = r"^\s*¥ USE[ \t]+(\S+)\s*$";
+
+lazy_static! {
+    static ref INCLUDE_RE: Regex = Regex::new(INCLUDE_MARKER).unwrap();
+    static ref SNIPPET_RE: Regex = Regex::new(SNIPPET_MARKER).unwrap();
+    static ref END_RE: Regex = Regex::new(END_MARKER).unwrap();
+    static ref USE_RE: Regex = Regex::new(USE_MARKER).unwrap();
+}
+
+/// Reads a pipeline file and returns its text with all includes and snippets expanded
+pub fn expand_file(text_fn: &str) -> String {
+    let scenario_dir = Path::new(text_fn).parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    let included = include_file(Path::new(text_fn), &scenario_dir, &mut Vec::new());
+    expand_snippets(&included)
+}
+
+/// The pipeline file and every file it includes, directly or not, each once and in order of inclusion
+pub fn source_files(text_fn: &str) -> Vec<PathBuf> {
+    let scenario_dir = Path::new(text_fn).parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    let mut ret = Vec::new();
+    collect_sources(Path::new(text_fn), &scenario_dir, &mut ret);
+    ret
+}
+
+fn collect_sources(text_fn: &Path, scenario_dir: &Path, found: &mut Vec<PathBuf>) {
+    let canonical = fs::canonicalize(text_fn).unwrap_or_else(|_| text_fn.to_path_buf());
+    if found.iter().any(|f| fs::canonicalize(f).unwrap_or_else(|_| f.clone()) == canonical) {
+        return;
+    }
+    found.push(text_fn.to_path_buf());
+    let text = fs::read_to_string(text_fn).unwrap_or_default();
+    for captures in text.lines().filter_map(|line| INCLUDE_RE.captures(line)) {
+        let candidates = include_candidates(captures.get(1).unwrap().as_str(), text_fn, scenario_dir);
+        if let Some(included) = candidates.iter().find(|c| c.is_file()) {
+            collect_sources(included, scenario_dir, found);
+        }
+    }
+}
+
+/// Candidate locations of an included file, in order of priority
+fn include_candidates(path: &str, including_fn: &Path, scenario_dir: &Path) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    if let Some(dir) = including_fn.parent() {
+        ret.push(dir.join(path));
+    }
+    ret.push(scenario_dir.join(path));
+    ret.push(Path::new(paths::COMMON_STEPS_PATH).join(path));
+    ret
+}
+
+fn include_file(text_fn: &Path, scenario_dir: &Path, stack: &mut Vec<PathBuf>) -> String {
+    let canonical = fs::canonicalize(text_fn).unwrap_or_else(|_| text_fn.to_path_buf());
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack.iter().chain(std::iter::once(&canonical)).map(|p| p.display().to_string()).collect();
+        panic!("Error: include cycle in {}: {}", paths::RUN_STEPS_FN, chain.join(" -> "));
+    }
+    let text = fs::read_to_string(text_fn).unwrap_or_else(|_| panic!("Error: cannot open {} properly. Check file permission and text encoding.", text_fn.display()));
+
+    stack.push(canonical);
+    let mut ret = String::with_capacity(text.len());
+    for (line_idx, line) in text.split_inclusive('\n').enumerate() {
+        match INCLUDE_RE.captures(line) {
+            Some(captures) => {
+                let path = captures.get(1).unwrap().as_str();
+                let candidates = include_candidates(path, text_fn, scenario_dir);
+                let found = candidates.iter().find(|c| c.is_file()).unwrap_or_else(|| {
+                    let tried: Vec<String> = candidates.iter().map(|c| c.display().to_string()).collect();
+                    panic!("Error: {}:{} includes '{}', which could not be found (looked at {})", text_fn.display(), line_idx + 1, path, tried.join(", "))
+                });
+                let included = include_file(found, scenario_dir, stack);
+                ret.push_str(&included);
+                if !included.is_empty() && !included.ends_with('\n') {
+                    ret.push('\n');
+                }
+            },
+            None => ret.push_str(line),
+        }
+    }
+    stack.pop();
+    ret
+}
+
+/// Removes the snippet definitions from the text, and replaces every use by the snippet content
+fn expand_snippets(text: &str) -> String {
+    let mut snippets: HashMap<String, String> = HashMap::new();
+    let mut body = String::with_capacity(text.len());
+
+    let mut current: Option<(String, String)> = None;
+    for line in text.split_inclusive('\n') {
+        if let Some(captures) = SNIPPET_RE.captures(line) {
+            if let Some((name, _)) = &current {
+                panic!("Error: in {}, snippet '{}' is not closed with '¥ END' before the next snippet starts", paths::RUN_STEPS_FN, name);
+            }
+            current = Some((captures.get(1).unwrap().as_str().to_string(), String::new()));
+        }
+        else if END_RE.is_match(line) {
+            let (name, content) = current.take().unwrap_or_else(|| panic!("Error: in {}, '¥ END' found outside of a snippet", paths::RUN_STEPS_FN));
+            if snippets.insert(name.clone(), content).is_some() {
+                panic!("Error: in {}, snippet '{}' is defined more than once", paths::RUN_STEPS_FN, name);
+            }
+        }
+        else {
+            match &mut current {
+                Some((_, content)) => content.push_str(line),
+                None => body.push_str(line),
+            }
+        }
+    }
+    if let Some((name, _)) = current {
+        panic!("Error: in {}, snippet '{}' is not closed with '¥ END'", paths::RUN_STEPS_FN, name);
+    }
+
+    use_snippets(&body, &snippets, &mut Vec::new())
+}
+
+fn use_snippets(text: &str, snippets: &HashMap<String, String>, stack: &mut Vec<String>) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        match USE_RE.captures(line) {
+            Some(captures) => {
+                let name = captures.get(1).unwrap().as_str().to_string();
+                if stack.contains(&name) {
+                    panic!("Error: in {}, snippet '{}' uses itself: {} -> {}", paths::RUN_STEPS_FN, name, stack.join(" -> "), name);
+                }
+                let content = snippets.get(&name).unwrap_or_else(|| panic!("Error: in {}, snippet '{}' is used but never defined", paths::RUN_STEPS_FN, name));
+                stack.push(name);
+                ret.push_str(&use_snippets(content, snippets, stack));
+                stack.pop();
+            },
+            None => ret.push_str(line),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh folder holding the given files
+    fn scenario(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("taas_include_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, content) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes() {
+        let dir = scenario("ok", &[
+            ("pipeline.run", "¥ RUN db\n  ¥ INCLUDE parts/setup.run\n¥ READY\n"),
+            ("parts/setup.run", "echo setup\n¥ INCLUDE steps.run"),
+            ("parts/steps.run", "echo step\n"),
+        ]);
+        let text = include_file(&dir.join("pipeline.run"), &dir, &mut Vec::new());
+        assert_eq!(text, "¥ RUN db\necho setup\necho step\n¥ READY\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sources() {
+        let dir = scenario("sources", &[
+            ("pipeline.run", "¥ INCLUDE a.run\n¥ INCLUDE parts/b.run\n¥ INCLUDE a.run\n"),
+            ("a.run", "echo a\n"),
+            ("parts/b.run", "¥ INCLUDE ../a.run\n"),
+        ]);
+        let pipeline_fn = dir.join("pipeline.run");
+        assert_eq!(source_files(pipeline_fn.to_str().unwrap()), vec![pipeline_fn.clone(), dir.join("a.run"), dir.join("parts/b.run")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "include cycle in")]
+    fn include_cycle() {
+        let dir = scenario("cycle", &[
+            ("pipeline.run", "¥ INCLUDE a.run\n"),
+            ("a.run", "echo a\n¥ INCLUDE b.run\n"),
+            ("b.run", "¥ INCLUDE ./a.run\n"),
+        ]);
+        include_file(&dir.join("pipeline.run"), &dir, &mut Vec::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "which could not be found")]
+    fn missing_include() {
+        let dir = scenario("missing", &[("pipeline.run", "¥ INCLUDE nowhere.run\n")]);
+        include_file(&dir.join("pipeline.run"), &dir, &mut Vec::new());
+    }
+
+    #[test]
+    fn snippets() {
+        let text = "¥ USE install\necho x # ¥ USE install\n¥ SNIPPET install\n  ¥ USE update\napt install -y x\n¥ END\n¥ SNIPPET update\napt update\n¥ END\n";
+        assert_eq!(expand_snippets(text), "apt update\napt install -y x\necho x # ¥ USE install\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "is used but never defined")]
+    fn undefined_snippet() {
+        expand_snippets("¥ USE nothing\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "uses itself: a -> b -> a")]
+    fn snippet_cycle() {
+        expand_snippets("¥ USE a\n¥ SNIPPET a\n¥ USE b\n¥ END\n¥ SNIPPET b\n¥ USE a\n¥ END\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "is not closed")]
+    fn unclosed_snippet() {
+        expand_snippets("¥ SNIPPET a\necho a\n");
+    }
+}
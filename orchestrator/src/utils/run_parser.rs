@@ -1,23 +1,52 @@
-use std::fs::File;
 use std::collections::HashMap;
-use std::io::{BufReader};
-use std::io::prelude::*;
+use std::collections::HashSet;
 use lazy_static::lazy_static;
 use regex::Regex;
 use regex::RegexSet;
 use std::fmt;
+use crate::pasir;
 
-const SETUP_MARKER: &str = r"¥ SETUP";
-const RUN_MARKER: &str = r"¥ RUN";
-const MACHINE_MARKER: &str = r"¥¥[ \t]*(.+)";
+// markers only count at the start of a line, so that scripts can contain them. '¥¥' followed by '{' is an escaped
+// ¥¥{token} in a script, not a machine marker, since the space after '¥¥' is optional ('¥¥db')
+const STEP_MARKER: &str = r"^\s*¥ (SETUP|RUN|UPLOAD|DOWNLOAD|REBOOT|WAIT|LOCAL)\b[ \t]*(.*)";
+const MACHINE_MARKER: &str = r"^\s*¥¥[ \t]*([^\s{].*)";
+const READY_MARKER: &str = r"^\s*¥ READY\b[ \t]*(.*)";
 
-const VM_LIST_RE: &str = r"¥¥\s*((?:\S+[ \t]*)+)";
+const VM_LIST_RE: &str = r"^\s*¥¥[ \t]*([^\s{]\S*(?:[ \t]+\S+)*)";
 const VM_LIST_SPLIT_RE: &str = r"[ \t]+";
 
+// machine selectors, e.g. '¥¥ role=client except client-3', '¥¥ db-*', '¥¥ all except db'
+const ALL_SELECTOR: &str = "all";
+const EXCEPT_SELECTOR: &str = "except";
+const ROLE_SELECTOR: &str = "role=";
+
+// step attributes, written after the step marker, e.g. '¥ RUN name=bench depends_on=install@self'
+const NAME_ATTR: &str = "name";
+const DEPENDS_ON_ATTR: &str = "depends_on";
+const REPEAT_ATTR: &str = "repeat";
+const SELF_MACHINE: &str = "self";
+
+// readiness check attributes, e.g. '¥ READY tcp server:8080 on=client timeout=120'
+const ON_ATTR: &str = "on";
+const TIMEOUT_ATTR: &str = "timeout";
+
+/// Pseudo machine name under which the steps running on the operator's host (WAIT, LOCAL) are recorded
+pub const HOST: &str = "@host";
+
 #[derive(Debug, Clone)]
 pub enum StepType {
     Setup,
     Run,
+    /// '¥ UPLOAD <local> <remote>': copies a file or folder of the scenario to the given path on the machines
+    Upload { local: String, remote: String },
+    /// '¥ DOWNLOAD <remote> [local]': fetches a file or folder from the machines into the downloads folder of the run
+    Download { remote: String, local: String },
+    /// '¥ REBOOT': restarts the machines and waits for them to be back
+    Reboot,
+    /// '¥ WAIT [seconds]': pauses on the operator's host, the step's readiness checks can be used to poll instead
+    Wait { seconds: u64 },
+    /// '¥ LOCAL': runs the script on the operator's host, with the same replacements as the post-deployment script
+    Local,
 }
 // Display trait for easier printing of the different steps
 impl fmt::Display for StepType {
@@ -25,119 +54,531 @@ impl fmt::Display for StepType {
         match self {
             StepType::Setup => write!(f, "setup"),
             StepType::Run => write!(f, "run"),
+            StepType::Upload { .. } => write!(f, "upload"),
+            StepType::Download { .. } => write!(f, "download"),
+            StepType::Reboot => write!(f, "reboot"),
+            StepType::Wait { .. } => write!(f, "wait"),
+            StepType::Local => write!(f, "local"),
         }
     }
 }
 
-/// Given a text file (as String) describing a sequence of machine scripts (setups and runs),
-/// Returns a data structure with each step encapsulated in a Vec and each script
-/// stored in a HashMap recording which machine sould run each of the scripts
-pub fn parse_run_list(text_fn: &str) -> Vec<(StepType, HashMap<String, String>)> {
+impl StepType {
+    /// Steps that run a script written in pipeline.run on each machine
+    pub fn has_machine_scripts(&self) -> bool {
+        matches!(self, StepType::Setup | StepType::Run)
+    }
+
+    /// Steps that run once on the operator's host rather than on machines
+    pub fn runs_on_host(&self) -> bool {
+        matches!(self, StepType::Wait { .. } | StepType::Local)
+    }
+
+    fn new(marker: &str, index: usize, args: &[&str]) -> Self {
+        let usage = |expected: &str| -> ! {
+            panic!("Error: in {}, step {} expects {} after {}", crate::paths::RUN_STEPS_FN, index, expected, marker)
+        };
+        match (marker, args) {
+            ("SETUP", []) => StepType::Setup,
+            ("RUN", []) => StepType::Run,
+            ("UPLOAD", [local, remote]) => StepType::Upload { local: local.to_string(), remote: remote.to_string() },
+            ("UPLOAD", _) => usage("a local path and a remote path"),
+            ("DOWNLOAD", [remote]) => StepType::Download { remote: remote.to_string(), local: String::new() },
+            ("DOWNLOAD", [remote, local]) => StepType::Download { remote: remote.to_string(), local: local.to_string() },
+            ("DOWNLOAD", _) => usage("a remote path, optionally followed by a local folder"),
+            ("REBOOT", []) => StepType::Reboot,
+            ("WAIT", []) => StepType::Wait { seconds: 0 },
+            ("WAIT", [seconds]) => StepType::Wait { seconds: seconds.parse().unwrap_or_else(|_| usage("a number of seconds")) },
+            ("WAIT", _) => usage("at most a number of seconds"),
+            ("LOCAL", []) => StepType::Local,
+            _ => usage("only key=value attributes"),
+        }
+    }
+}
+
+/// Which machines of another step a dependency waits for
+#[derive(Debug, Clone, PartialEq)]
+pub enum DependencyTarget {
+    /// 'step': the step must be finished on all of its machines
+    AllMachines,
+    /// 'step@self': the step must be finished on the same machine
+    SameMachine,
+    /// 'step@machine': the step must be finished on the given machine
+    Machine(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub step: String,
+    pub target: DependencyTarget,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckKind {
+    /// 'host:port' accepts TCP connections, host being an IP address or a machine name
+    Tcp,
+    /// the URL answers with HTTP 200
+    Http,
+    /// the shell command exits with 0
+    Cmd,
+    /// the file exists
+    File,
+}
+
+/// A condition polled after a step has finished, before the steps depending on it can start
+#[derive(Debug, Clone)]
+pub struct ReadinessCheck {
+    pub kind: CheckKind,
+    pub target: String,
+    /// Machine polling the check. None means every machine of the step
+    pub on: Option<String>,
+    pub timeout_secs: u64,
+}
+
+impl fmt::Display for ReadinessCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            CheckKind::Tcp => "tcp",
+            CheckKind::Http => "http",
+            CheckKind::Cmd => "cmd",
+            CheckKind::File => "file",
+        };
+        write!(f, "{} {}", kind, self.target)
+    }
+}
+
+impl ReadinessCheck {
+    fn parse(step_name: &str, text: &str) -> Self {
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() {
+            panic!("Error: in {}, step '{}' has an empty readiness check", crate::paths::RUN_STEPS_FN, step_name);
+        }
+        let kind = match words.remove(0).to_lowercase().as_ref() {
+            "tcp" => CheckKind::Tcp,
+            "http" => CheckKind::Http,
+            "cmd" => CheckKind::Cmd,
+            "file" => CheckKind::File,
+            other => panic!("Error: in {}, step '{}' has an unknown readiness check '{}' (expected tcp, http, cmd or file)", crate::paths::RUN_STEPS_FN, step_name, other),
+        };
+
+        // the attributes are at the end of the line, everything before them is the target (which may contain spaces for commands)
+        let mut on = None;
+        let mut timeout_secs = crate::utils::global_config::DEFAULT_VALUES.readiness_timeout_secs;
+        while let Some(last) = words.last() {
+            if let Some(machine) = last.strip_prefix(&format!("{}=", ON_ATTR)) {
+                on = Some(machine.to_string());
+            }
+            else if let Some(timeout) = last.strip_prefix(&format!("{}=", TIMEOUT_ATTR)) {
+                timeout_secs = timeout.parse().unwrap_or_else(|_| panic!("Error: in {}, step '{}' has a readiness check with an invalid timeout '{}'", crate::paths::RUN_STEPS_FN, step_name, timeout));
+            }
+            else {
+                break;
+            }
+            words.pop();
+        }
+
+        let target = words.join(" ");
+        let valid = match kind {
+            CheckKind::Tcp => target.rfind(':').map(|pos| target[pos+1..].parse::<u16>().is_ok()).unwrap_or(false),
+            _ => !target.is_empty(),
+        };
+        if !valid {
+            panic!("Error: in {}, step '{}' has an invalid readiness check '{}'", crate::paths::RUN_STEPS_FN, step_name, text.trim());
+        }
 
-    let mut ret = Vec::new();
-    fn is_eof(res: Result<usize, std::io::Error>) -> bool {
-        let eof = res.is_err() || res.unwrap() == 0;
-        eof
+        ReadinessCheck {
+            kind,
+            target,
+            on,
+            timeout_secs,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub step_type: StepType,
+    /// Name used to reference the step in dependencies. Defaults to the index of the step
+    pub name: String,
+    /// None means the step simply waits for the previous one to be finished everywhere
+    pub depends_on: Option<Vec<Dependency>>,
+    /// Script to run, for every machine taking part in the step
+    pub scripts: HashMap<String, String>,
+    /// Checks that must pass before the step is considered finished
+    pub checks: Vec<ReadinessCheck>,
+    /// Number of times a RUN step is executed on each machine, None to use the default of the run
+    pub repeat: Option<usize>,
+}
+
+impl Step {
+    fn new(marker: &str, index: usize, attributes: &str) -> Self {
+        let mut name = index.to_string();
+        let mut depends_on = None;
+        let mut repeat = None;
+        let mut args = Vec::new();
+
+        for attr in attributes.split_whitespace() {
+            let (key, value) = match attr.find('=') {
+                Some(pos) => (&attr[..pos], &attr[pos+1..]),
+                None => {
+                    // arguments of the step kind, e.g. the paths of an upload
+                    args.push(attr);
+                    continue;
+                },
+            };
+            match key {
+                NAME_ATTR => name = value.to_string(),
+                DEPENDS_ON_ATTR => depends_on = Some(value.split(',').filter(|d| !d.is_empty()).map(parse_dependency).collect()),
+                REPEAT_ATTR => repeat = Some(value.parse().ok().filter(|n| *n > 0).unwrap_or_else(|| panic!("Error: in {}, step {} has an invalid repeat count '{}'", crate::paths::RUN_STEPS_FN, index, value))),
+                _ => panic!("Error: in {}, step {} has an unknown attribute '{}'", crate::paths::RUN_STEPS_FN, index, key),
+            }
+        }
+
+        let step_type = StepType::new(marker, index, &args);
+        if repeat.is_some() && !matches!(step_type, StepType::Run) {
+            panic!("Error: in {}, step {} is a {} step, only run steps can be repeated", crate::paths::RUN_STEPS_FN, index, step_type);
+        }
+        let mut scripts = HashMap::new();
+        if step_type.runs_on_host() {
+            scripts.insert(HOST.to_string(), String::new());
+        }
+
+        Step {
+            step_type,
+            name,
+            depends_on,
+            scripts,
+            checks: Vec::new(),
+            repeat,
+        }
+    }
+}
+
+fn parse_dependency(dep: &str) -> Dependency {
+    match dep.find('@') {
+        Some(pos) => {
+            let machine = &dep[pos+1..];
+            Dependency {
+                step: dep[..pos].to_string(),
+                target: if machine == SELF_MACHINE { DependencyTarget::SameMachine } else { DependencyTarget::Machine(machine.to_string()) },
+            }
+        },
+        None => Dependency { step: dep.to_string(), target: DependencyTarget::AllMachines },
+    }
+}
+
+/// Returns true if the name matches the glob pattern, where '*' matches any sequence of characters and '?' any single one
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // position of the last '*' in the pattern, and of the name when it was reached
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        }
+        else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        }
+        else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        }
+        else {
+            return false;
+        }
     }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Resolves the selectors of a '¥¥' line into machine names, in the order the machines are declared.
+/// Every selector must match at least one machine, so that typos are caught before deploying anything.
+fn resolve_machines(selectors: &[&str], vms: &[pasir::machines::Vm]) -> Vec<String> {
+    let resolve = |selector: &str| -> Vec<usize> {
+        let matched: Vec<usize> = vms.iter().enumerate().filter(|(_, vm)| {
+            if selector == ALL_SELECTOR {
+                true
+            }
+            else if let Some(role) = selector.strip_prefix(ROLE_SELECTOR) {
+                vm.roles.iter().any(|r| r == role)
+            }
+            else {
+                glob_match(selector, &vm.name)
+            }
+        }).map(|(idx, _)| idx).collect();
+        if matched.is_empty() {
+            panic!("Error: in {}, '{}' does not match any machine declared in the system description", crate::paths::RUN_STEPS_FN, selector);
+        }
+        matched
+    };
+
+    let (included, excluded) = match selectors.iter().position(|s| *s == EXCEPT_SELECTOR) {
+        Some(pos) => (&selectors[..pos], &selectors[pos+1..]),
+        None => (selectors, &[][..]),
+    };
+    let excluded: HashSet<usize> = excluded.iter().flat_map(|s| resolve(s)).collect();
+    let included: HashSet<usize> = included.iter().flat_map(|s| resolve(s)).collect();
+
+    vms.iter().enumerate().filter(|(idx, _)| included.contains(idx) && !excluded.contains(idx)).map(|(_, vm)| vm.name.clone()).collect()
+}
+
+/// Given a text file (as String) describing a sequence of machine scripts (setups and runs),
+/// Returns a data structure with each step encapsulated in a Step and each script
+/// stored in a HashMap recording which machine sould run each of the scripts.
+/// Machine selectors are resolved against the given machines.
+pub fn parse_run_list(text_fn: &str, vms: &[pasir::machines::Vm]) -> Vec<Step> {
+    let text = crate::utils::run_include::expand_file(text_fn);
+    let ret = parse_run_text(&text, vms);
+    check_dependencies(&ret);
+    ret
+}
+
+fn parse_run_text(text: &str, vms: &[pasir::machines::Vm]) -> Vec<Step> {
 
     lazy_static! {
         static ref PARSE_RE_SET: RegexSet = RegexSet::new(&[
-            SETUP_MARKER,
-            RUN_MARKER,
+            STEP_MARKER,
             MACHINE_MARKER,
+            READY_MARKER,
         ]).unwrap();
 
-        static ref MACHINE_NAME_RE: Regex = Regex::new(MACHINE_MARKER).unwrap();
+        static ref STEP_RE: Regex = Regex::new(STEP_MARKER).unwrap();
+        static ref READY_RE: Regex = Regex::new(READY_MARKER).unwrap();
         static ref VM_RE: Regex = Regex::new(VM_LIST_RE).unwrap();
         static ref VM_SPLIT_RE: Regex = Regex::new(VM_LIST_SPLIT_RE).unwrap();
     }
 
-    let f = File::open(text_fn).unwrap();
-    let mut f = BufReader::new(f);
-    let mut line_buffer = String::with_capacity(64); //creating string with some capacity to reduce the number of re-allocations
+    fn flush_script(step: &mut Option<Step>, machines: &[String], script: &mut String) {
+        if let Some(step) = step {
+            if step.step_type.has_machine_scripts() {
+                if !script.is_empty() {
+                    machines.iter().for_each(|n| {
+                        step.scripts.insert(n.to_string(), script.clone());
+                    });
+                }
+            }
+            else if let StepType::Local = step.step_type {
+                step.scripts.get_mut(HOST).unwrap().push_str(script);
+            }
+            else if !script.trim().is_empty() {
+                panic!("Error: in {}, step '{}' is a {} step, which does not take a script", crate::paths::RUN_STEPS_FN, step.name, step.step_type);
+            }
+        }
+        script.clear();
+    }
 
-    let mut state: Option<StepType> = None;
+    let mut ret: Vec<Step> = Vec::new();
+    let mut current_step: Option<Step> = None;
 
     // run through text line by line, checking if there is a match for one of the re
     // whenever there is a new re match, absorb all lines until new re match to that specific context
     // otherwise just record the line and go to the next one
     let mut current_machine_list: Vec<String> = Vec::new();
     let mut current_script = String::from("");
-    let mut current_step = HashMap::new();
-    while {
-
-        let line_res = f.read_line(&mut line_buffer);
+    for line in text.split_inclusive('\n') {
 
         // check if current line is a special one
-        let possible_matches: Vec<_> = PARSE_RE_SET.matches(&line_buffer).into_iter().collect();
-        if !possible_matches.is_empty() {
-            debug_assert!(possible_matches.len() <= 1);
-            match possible_matches[0] {
-                0 => { // Setup Marker
-                    if !current_script.is_empty() {
-                        (&current_machine_list).into_iter().for_each(|n| {
-                            current_step.insert(n.to_string(), current_script.clone());
-                        });
-
-                        current_script.clear();
-                    }
-                    if state.is_some() {
-                        ret.push( (state.unwrap(), current_step.clone()) );
-                    }
-                    
-                    current_step.clear();
-                    state = Some(StepType::Setup);
-                },
-                1 => { // Run marker
-                    if !current_script.is_empty() {
-                        (&current_machine_list).into_iter().for_each(|n| {
-                            current_step.insert(n.to_string(), current_script.clone());
-                        });
-                        current_script.clear();
-                    }
-                    if state.is_some() {
-                        ret.push( (state.unwrap(), current_step.clone()) );
-                    }
-                    current_step.clear();
-                    state = Some(StepType::Run);
-                },
-                2 => { // Machine marker
-                    if !current_script.is_empty() {
-                        (&current_machine_list).into_iter().for_each(|n| {
-                            current_step.insert(n.to_string(), current_script.clone());
-                        });
-                        current_script.clear();
-                    }
-                    let current_machine_string = VM_RE.captures(&line_buffer).unwrap().get(1).unwrap().as_str();
-                    current_machine_list = VM_SPLIT_RE.split(current_machine_string).map(|v| v.to_string()).collect();
-
-                },
-                _ => panic!() //should not happen
+        let possible_matches: Vec<_> = PARSE_RE_SET.matches(line).into_iter().collect();
+        if possible_matches.is_empty() {
+            if current_step.is_some() {
+                current_script.push_str(line);
             }
-            line_buffer.clear() //clear the string now so special lines are not added to the scripts
+            continue;
+        }
+
+        debug_assert!(possible_matches.len() <= 1);
+
+        // readiness checks do not interrupt the script they are written in
+        if possible_matches[0] == 2 {
+            let step = current_step.as_mut().unwrap_or_else(|| panic!("Error: in {}, a readiness check is declared before the first step", crate::paths::RUN_STEPS_FN));
+            let check = ReadinessCheck::parse(&step.name, READY_RE.captures(line).unwrap().get(1).unwrap().as_str());
+            step.checks.push(check);
+            continue;
         }
 
-        // add current line to script buffer
-        match &state {
-            None => {},
-            Some(StepType::Run) => {
-                current_script.push_str(&line_buffer);
+        flush_script(&mut current_step, &current_machine_list, &mut current_script);
+        match possible_matches[0] {
+            0 => { // Step marker
+                if let Some(step) = current_step.take() {
+                    ret.push(step);
+                }
+                let captures = STEP_RE.captures(line).unwrap();
+                current_step = Some(Step::new(captures.get(1).unwrap().as_str(), ret.len(), captures.get(2).unwrap().as_str()));
             },
-            Some(StepType::Setup) => {
-                current_script.push_str(&line_buffer);
+            1 => { // Machine marker
+                let current_machine_string = VM_RE.captures(line).unwrap().get(1).unwrap().as_str();
+                let selectors: Vec<&str> = VM_SPLIT_RE.split(current_machine_string.trim()).collect();
+                current_machine_list = resolve_machines(&selectors, vms);
+
+                // steps without scripts still need to know which machines they apply to
+                if let Some(step) = current_step.as_mut() {
+                    if step.step_type.runs_on_host() {
+                        panic!("Error: in {}, step '{}' runs on the operator's host and cannot be given machines", crate::paths::RUN_STEPS_FN, step.name);
+                    }
+                    if !step.step_type.has_machine_scripts() {
+                        current_machine_list.iter().for_each(|n| { step.scripts.insert(n.to_string(), String::new()); });
+                    }
+                }
             },
-        };
-        
-        line_buffer.clear();
-        let eof = is_eof(line_res);
-        if eof { //add last step
-            (&current_machine_list).into_iter().for_each(|n| {
-                current_step.insert(n.to_string(), current_script.clone());
-            });
-            ret.push( (state.as_ref().unwrap().clone(), current_step.clone()) );
-        }
-        !eof
-    }
-    {}
+            _ => panic!() //should not happen
+        }
+    }
+
+    //add last step
+    flush_script(&mut current_step, &current_machine_list, &mut current_script);
+    if let Some(step) = current_step {
+        ret.push(step);
+    }
     ret
-}
\ No newline at end of file
+}
+
+/// Makes sure step names are unique, every dependency references an existing step, and that the steps form a DAG
+fn check_dependencies(steps: &[Step]) {
+
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    for (idx, step) in steps.iter().enumerate() {
+        if names.insert(&step.name, idx).is_some() {
+            panic!("Error: in {}, the step name '{}' is used more than once", crate::paths::RUN_STEPS_FN, step.name);
+        }
+    }
+
+    for step in steps {
+        if (step.scripts.is_empty() || step.step_type.runs_on_host()) && step.checks.iter().any(|c| c.on.is_none()) {
+            panic!("Error: in {}, step '{}' has no machines, so its readiness checks need to say where they run with 'on=<machine>'", crate::paths::RUN_STEPS_FN, step.name);
+        }
+        for dep in step.depends_on.iter().flatten() {
+            let dep_idx = names.get(dep.step.as_str()).unwrap_or_else(|| panic!("Error: in {}, step '{}' depends on step '{}', which does not exist", crate::paths::RUN_STEPS_FN, step.name, dep.step));
+            if let DependencyTarget::Machine(machine) = &dep.target {
+                if !steps[*dep_idx].scripts.contains_key(machine) {
+                    panic!("Error: in {}, step '{}' depends on step '{}' on machine '{}', but that machine does not take part in it", crate::paths::RUN_STEPS_FN, step.name, dep.step, machine);
+                }
+            }
+        }
+    }
+
+    // depth-first search for cycles, keeping the current path to report it
+    fn visit(idx: usize, steps: &[Step], names: &HashMap<&str, usize>, done: &mut HashSet<usize>, path: &mut Vec<usize>) {
+        if done.contains(&idx) {
+            return;
+        }
+        if let Some(pos) = path.iter().position(|p| *p == idx) {
+            let cycle: Vec<&str> = path[pos..].iter().chain(std::iter::once(&idx)).map(|i| steps[*i].name.as_str()).collect();
+            panic!("Error: in {}, the step dependencies contain a cycle: {}", crate::paths::RUN_STEPS_FN, cycle.join(" -> "));
+        }
+        path.push(idx);
+        for dep_idx in direct_dependencies(idx, steps, names) {
+            visit(dep_idx, steps, names, done, path);
+        }
+        path.pop();
+        done.insert(idx);
+    }
+
+    let mut done = HashSet::new();
+    for idx in 0..steps.len() {
+        visit(idx, steps, &names, &mut done, &mut Vec::new());
+    }
+}
+
+/// Indices of the steps a step directly depends on (the previous one if it has no explicit dependencies)
+fn direct_dependencies(idx: usize, steps: &[Step], names: &HashMap<&str, usize>) -> Vec<usize> {
+    match &steps[idx].depends_on {
+        Some(deps) => deps.iter().map(|d| names[d.step.as_str()]).collect(),
+        None if idx > 0 => vec![idx - 1],
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vm(name: &str, roles: &[&str]) -> pasir::machines::Vm {
+        pasir::machines::Vm {
+            name: name.to_string(),
+            os: pasir::machines::Os { candidates: crate::asir::OsCandidates::common_only("UbuntuLTS") },
+            hwconfig: None,
+            override_config: None,
+            config_template: String::new(),
+            has_remote_access: false,
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            auth: pasir::machines::Auth { user: "taasuser".to_string(), password: String::new(), ssh_key: None },
+        }
+    }
+
+    fn vms() -> Vec<pasir::machines::Vm> {
+        vec![vm("db", &["db"]), vm("client-1", &["client"]), vm("client-2", &["client"]), vm("client-3", &["client"])]
+    }
+
+    fn machines(step: &Step) -> Vec<String> {
+        let mut machines: Vec<String> = step.scripts.keys().cloned().collect();
+        machines.sort();
+        machines
+    }
+
+    #[test]
+    fn markers_inside_scripts_are_script_lines() {
+        let steps = parse_run_text("¥ RUN\n¥¥ db\necho ¥¥{literal}\necho '¥ RUN is a marker' ¥¥ db\n", &vms());
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].scripts["db"], "echo ¥¥{literal}\necho '¥ RUN is a marker' ¥¥ db\n");
+    }
+
+    #[test]
+    fn indented_markers() {
+        let steps = parse_run_text("  ¥ SETUP\n  ¥¥ db\ntrue\n", &vms());
+        assert_eq!(machines(&steps[0]), vec!["db"]);
+    }
+
+    #[test]
+    fn machine_markers_without_space() {
+        let steps = parse_run_text("¥ RUN\n¥¥db client-1\n¥¥{literal}\n", &vms());
+        assert_eq!(machines(&steps[0]), vec!["client-1", "db"]);
+        assert_eq!(steps[0].scripts["db"], "¥¥{literal}\n");
+    }
+
+    #[test]
+    fn selectors() {
+        let steps = parse_run_text("¥ RUN\n¥¥ role=client except client-3\na\n¥ RUN\n¥¥ all except db\nb\n¥ RUN\n¥¥ client-? db\nc\n", &vms());
+        assert_eq!(machines(&steps[0]), vec!["client-1", "client-2"]);
+        assert_eq!(machines(&steps[1]), vec!["client-1", "client-2", "client-3"]);
+        assert_eq!(machines(&steps[2]), vec!["client-1", "client-2", "client-3", "db"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not match any machine")]
+    fn unknown_selector() {
+        parse_run_text("¥ RUN\n¥¥ web\ntrue\n", &vms());
+    }
+
+    #[test]
+    fn attributes_and_dependencies() {
+        let steps = parse_run_text("¥ SETUP name=install\n¥¥ all\ntrue\n¥ RUN name=bench repeat=3 depends_on=install@self,install@db\n¥¥ role=client\ntrue\n", &vms());
+        check_dependencies(&steps);
+        assert_eq!(steps[1].name, "bench");
+        assert_eq!(steps[1].repeat, Some(3));
+        let deps = steps[1].depends_on.as_ref().unwrap();
+        assert_eq!(deps[0].step, "install");
+        assert!(matches!(deps[0].target, DependencyTarget::SameMachine));
+        assert!(matches!(&deps[1].target, DependencyTarget::Machine(m) if m == "db"));
+    }
+
+    #[test]
+    #[should_panic(expected = "contain a cycle: a -> b -> a")]
+    fn dependency_cycle() {
+        let steps = parse_run_text("¥ RUN name=a depends_on=b\n¥¥ db\ntrue\n¥ RUN name=b depends_on=a\n¥¥ db\ntrue\n", &vms());
+        check_dependencies(&steps);
+    }
+
+    #[test]
+    fn readiness_checks() {
+        let steps = parse_run_text("¥ RUN\n¥¥ db\nstart\n¥ READY tcp db:5432 on=client-1 timeout=30\nmore\n", &vms());
+        assert_eq!(steps[0].scripts["db"], "start\nmore\n");
+        let check = &steps[0].checks[0];
+        assert_eq!(check.kind, CheckKind::Tcp);
+        assert_eq!(check.target, "db:5432");
+        assert_eq!(check.on.as_deref(), Some("client-1"));
+        assert_eq!(check.timeout_secs, 30);
+    }
+}
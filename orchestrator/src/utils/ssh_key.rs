@@ -0,0 +1,52 @@
+// utils/ssh_key.rs
+//
+// Every run has its own SSH key pair (runs/<run id>/ssh_key), generated the first time it is needed. It is installed on
+// the orchestrator and on the machines without an `ssh_key` of their own in system.yml, and the orchestrator uses it
+// for every ssh, scp and rsync to them. The users of the machines also get passwordless sudo when they are provisioned,
+// so nothing has to send a password over ssh.
+
+use std::fs;
+use std::path::Path;
+use crate::paths;
+use crate::shell_tools;
+use crate::shell_tools::RunInfo;
+use crate::utils::global_config::{SHELL, SSH};
+
+/// Path of the private key of the current run, generated on first use
+pub fn run_key() -> String {
+    let key_fn = crate::state::run_path(paths::RUN_SSH_KEY_FN);
+    if !Path::new(&key_fn).exists() {
+        shell_tools::run_command(&format!("ssh-keygen -q -t ed25519 -N '' -C taas-{} -f {}", crate::state::run_id(), key_fn), &SHELL.shell).panic_on_failure();
+    }
+    key_fn
+}
+
+/// Expands a leading ~/ to the home folder of the operator
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
+
+/// Path of the public key going with a private key, which has to exist
+pub fn public_key(private_key: &str) -> String {
+    let public_fn = format!("{}.pub", private_key);
+    if !Path::new(&public_fn).exists() {
+        panic!("Error: could not find the public key {} of the ssh key {}", public_fn, private_key);
+    }
+    public_fn
+}
+
+/// Options of ssh and scp (without the user and host) to log in with the given private key
+pub fn ssh_args(private_key: &str) -> String {
+    format!("{} -i {} -oIdentitiesOnly=yes -oStrictHostKeyChecking=no -oUserKnownHostsFile=/dev/null", SSH.custom_args, private_key)
+}
+
+/// Writes the cloud-init file giving passwordless sudo to a user at provisioning, and returns its path
+pub fn sudo_cloud_init(user: &str) -> String {
+    let init_fn = crate::state::run_path(&format!("{}_{}.yml", paths::SUDO_CLOUD_INIT_PREFIX, user));
+    let content = format!("#cloud-config\nwrite_files:\n  - path: /etc/sudoers.d/90-taas-{user}\n    permissions: '0440'\n    content: |\n      {user} ALL=(ALL) NOPASSWD:ALL\n", user = user);
+    fs::write(&init_fn, content).unwrap_or_else(|_| panic!("Error: could not write {}. Please check permissions", init_fn));
+    init_fn
+}
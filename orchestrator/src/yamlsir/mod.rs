@@ -4,6 +4,7 @@ use serde::{Serialize, Deserialize};
 use std::fs;
 use crate::utils::global_config::DEFAULT_VALUES;
 use std::io::Read;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 #[macro_use]
@@ -17,6 +18,9 @@ pub struct Root {
     pub options: Option<HashSet<String>>,
     #[serde(default = "no_connections")]
     pub connections: Vec<Connection>,
+    pub teardown: Option<Teardown>,
+    /// How the files reach the machines: webserver, direct or auto (see pipelines/distribution.rs)
+    pub distribution: Option<String>,
 }
 
 fn no_connections() -> Vec<Connection> {
@@ -24,6 +28,7 @@ fn no_connections() -> Vec<Connection> {
 }
 
 make_default!(config_template, String);
+make_default!(username, String);
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Machine {
 
@@ -31,18 +36,43 @@ pub struct Machine {
     pub os_common: String,
     pub hwconfig: Option<HwConfig>,
     pub override_config: Option<HashMap<String, String>>,
-    pub auth: Auth,
+    /// Default user and a password generated for the run when omitted
+    pub auth: Option<Auth>,
     pub remote_access: bool,
     #[serde(default = "config_template")]
     pub config_template: String,
-    pub role: String,
+    /// Single role, kept for older system descriptions
+    pub role: Option<String>,
+    pub roles: Option<Roles>,
+}
+
+/// The roles of a machine, with the attributes the templates of its peers can read
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Roles {
+    /// `roles: [db, monitoring]`
+    Names(Vec<String>),
+    /// `roles: {db: {port: 5432}, monitoring: }`
+    WithAttributes(BTreeMap<String, Option<BTreeMap<String, serde_yaml::Value>>>),
+}
+
+/// What to do with the cloud resources once the run is over (see the teardown module)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Teardown {
+
+    pub policy: Option<String>,
+    pub ttl_hours: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Auth {
 
+    #[serde(default = "username")]
     pub username: String,
-    pub password: String,
+    /// Generated for the run when omitted
+    pub password: Option<String>,
+    /// Private key to log in with, next to its .pub; the key pair of the run when omitted
+    pub ssh_key: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -0,0 +1,26 @@
+#!/bin/sh
+# Releases the barriers used by the machine scripts (see taas_barrier.sh).
+# A machine reaching barrier <name> uploads machine_reports/<machine>/barrier.<name>, which contains the
+# number of machines expected at the barrier. Once that many machines have reached it, barriers/<name>
+# is created, and the waiting machines (polling it through the webserver) carry on.
+# A shared orchestrator holds the files of every run in runs/<run id>/, each with its own barriers.
+release_barriers() {
+    mkdir -p barriers
+    names=$(for f in machine_reports/*/barrier.*; do [ -e "$f" ] && basename "$f" | sed 's/^barrier\.//'; done | sort -u)
+    for name in $names; do
+        [ -e barriers/$name ] && continue
+        reached=$(ls machine_reports/*/barrier.$name | wc -l)
+        expected=$(cat machine_reports/*/barrier.$name | sort -n | tail -n 1)
+        if [ -n "$expected" ] && [ "$reached" -ge "$expected" ]; then
+            echo "$reached" > barriers/$name
+        fi
+    done
+}
+
+cd /home/orch
+while true; do
+    for dir in . runs/*; do
+        [ -d "$dir/machine_reports" ] && (cd "$dir" && release_barriers)
+    done
+    sleep 1
+done
@@ -0,0 +1,25 @@
+#!/bin/sh
+# Usage: sh taas_barrier.sh <barrier name> <number of machines>
+# Blocks until <number of machines> machines (this one included) have reached the barrier with the given name.
+# This file is a template: it is copied to every machine with the webserver URL and machine name filled in.
+name=$1
+count=$2
+if [ -z "$name" ] || [ -z "$count" ]; then
+    echo "Usage: sh taas_barrier.sh <barrier name> <number of machines>" >&2
+    exit 1
+fi
+
+ws=¥{WEBSERVER_URL}
+auth="Authorization: Bearer $(cat /etc/taas_token)"
+report=/tmp/taas_barrier.$name
+echo "$count" > $report
+
+echo "Waiting at barrier $name for $count machines..."
+if command -v curl > /dev/null; then
+    until curl -sf -H "$auth" -T $report $ws/machine_reports/¥{machine_name}/barrier.$name > /dev/null; do sleep 1; done
+    until curl -sf -H "$auth" -o /dev/null $ws/barriers/$name; do sleep 1; done
+else
+    until wget -q -O /dev/null --header="$auth" --method=PUT --body-file=$report $ws/machine_reports/¥{machine_name}/barrier.$name; do sleep 1; done
+    until wget -q -O /dev/null --header="$auth" $ws/barriers/$name; do sleep 1; done
+fi
+echo "Barrier $name released"
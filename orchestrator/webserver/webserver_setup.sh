@@ -1,5 +1,8 @@
 #!/usr/bin/env bash
 chmod +x /home/orch/ws
 sudo cp ~/orche.service /etc/systemd/system/orche.service
-sudo systemctl enable orche
-sudo systemctl restart orche
\ No newline at end of file
+sudo cp ~/orche-barrier.service /etc/systemd/system/orche-barrier.service
+sudo systemctl daemon-reload
+sudo systemctl enable orche orche-barrier
+sudo systemctl restart orche
+sudo systemctl restart orche-barrier
\ No newline at end of file